@@ -0,0 +1,192 @@
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::mat4::Mat4;
+use crate::quat::Quat;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+// How many poses per keyframe span get folded into the bounding box. Rotation
+// sweeps corners along arcs, and the padding that covers the arc between two
+// poses shrinks the closer together they are.
+const BOX_SAMPLES: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Interpolation {
+  Step,
+  Linear,
+  // Eases in and out of every keyframe
+  Smooth,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+  pub time: f64,
+  pub translation: Vec3,
+  pub rotation: Quat,
+  pub scale: Vec3,
+}
+
+impl Keyframe {
+  pub fn new(time: f64, translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+    Self {
+      time,
+      translation,
+      rotation,
+      scale,
+    }
+  }
+
+  // Scale first, then rotate, then translate
+  pub fn matrix(&self) -> Mat4 {
+    Mat4::translation(self.translation) * self.rotation.to_mat4() * Mat4::scale(self.scale)
+  }
+
+  pub fn inverse_matrix(&self) -> Mat4 {
+    Mat4::scale(scalar(1.0) / self.scale)
+      * self.rotation.to_mat4().transpose()
+      * Mat4::translation(-self.translation)
+  }
+
+  fn lerp(&self, other: &Self, t: f64, time: f64) -> Self {
+    Self {
+      time,
+      translation: self.translation + scalar(t) * (other.translation - self.translation),
+      rotation: self.rotation.slerp(other.rotation, t),
+      scale: self.scale + scalar(t) * (other.scale - self.scale),
+    }
+  }
+}
+
+// Any hitable moved along keyframes, evaluated at each ray's time. Times
+// before the first or after the last keyframe hold that pose.
+#[derive(Debug)]
+pub struct Animated {
+  hitable: Box<dyn Hitable>,
+  keyframes: Vec<Keyframe>,
+  interpolation: Interpolation,
+}
+
+impl Animated {
+  pub fn new(
+    hitable: Box<dyn Hitable>,
+    mut keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+  ) -> Self {
+    assert!(
+      !keyframes.is_empty(),
+      "Animated needs at least one keyframe"
+    );
+    keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).expect("NaN keyframe time"));
+    Self {
+      hitable,
+      keyframes,
+      interpolation,
+    }
+  }
+
+  pub fn sample(&self, time: f64) -> Keyframe {
+    let first = &self.keyframes[0];
+    let last = &self.keyframes[self.keyframes.len() - 1];
+    if time <= first.time {
+      return Keyframe { time, ..*first };
+    }
+    if time >= last.time {
+      return Keyframe { time, ..*last };
+    }
+
+    let next = self.keyframes.iter().position(|k| k.time > time).unwrap();
+    let k0 = &self.keyframes[next - 1];
+    let k1 = &self.keyframes[next];
+    let t = (time - k0.time) / (k1.time - k0.time);
+    let t = match self.interpolation {
+      Interpolation::Step => 0.0,
+      Interpolation::Linear => t,
+      Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+    };
+    k0.lerp(k1, t, time)
+  }
+
+  fn sample_times(&self, t0: f64, t1: f64) -> Vec<f64> {
+    let mut times = vec![t0, t1];
+    times.extend(
+      self
+        .keyframes
+        .iter()
+        .map(|k| k.time)
+        .filter(|&time| time > t0 && time < t1),
+    );
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut samples = vec![];
+    for pair in times.windows(2) {
+      for i in 0..BOX_SAMPLES {
+        samples.push(pair[0] + (pair[1] - pair[0]) * i as f64 / BOX_SAMPLES as f64);
+      }
+    }
+    samples.push(t1);
+    samples
+  }
+}
+
+impl Hitable for Animated {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let pose = self.sample(r.time());
+    transformed_hit(
+      &*self.hitable,
+      &pose.matrix(),
+      &pose.inverse_matrix(),
+      r,
+      t_min,
+      t_max,
+    )
+  }
+
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    let pose = self.sample(r.time());
+    transformed_intervals(&*self.hitable, &pose.matrix(), &pose.inverse_matrix(), r)
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    let pose = self.sample(r.time());
+    self
+      .hitable
+      .occluded(&local_ray(&pose.inverse_matrix(), r), t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    let boxy = self.hitable.bounding_box(t0, t1)?;
+    let poses = self
+      .sample_times(t0, t1)
+      .into_iter()
+      .map(|time| self.sample(time))
+      .collect::<Vec<_>>();
+
+    let mut result = poses[0].matrix().transform_aabb(boxy);
+    for pair in poses.windows(2) {
+      // Between two samples a point r from the pivot strays at most r times
+      // the angle turned off the line between where the samples put it, so
+      // that much padding covers the arc its corners sweep
+      let angle = 2.0 * pair[0].rotation.dot(pair[1].rotation).abs().min(1.0).acos();
+      let reach = corner_reach(boxy, pair[0].scale).max(corner_reach(boxy, pair[1].scale));
+      let pad = scalar(reach * angle);
+      let pair_box = surrounding_box(
+        pair[0].matrix().transform_aabb(boxy),
+        pair[1].matrix().transform_aabb(boxy),
+      );
+      result = surrounding_box(result, Aabb::new(pair_box.min - pad, pair_box.max + pad));
+    }
+    Some(result)
+  }
+}
+
+// Farthest any point of `boxy` gets from the origin once scaled
+fn corner_reach(boxy: Aabb, scale: Vec3) -> f64 {
+  let mut squared = 0.0;
+  for a in 0..3 {
+    let far = (scale[a] * boxy.min[a])
+      .abs()
+      .max((scale[a] * boxy.max[a]).abs());
+    squared += far * far;
+  }
+  squared.sqrt()
+}
@@ -1,8 +1,10 @@
-use rand::prelude::*;
 use std::cmp::Ordering;
+use std::sync::Arc;
+use std::thread;
 
 use crate::hitable::*;
 use crate::ray::*;
+use crate::stats::{self, TreeStats};
 use crate::vec3::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -16,6 +18,32 @@ impl Aabb {
     Self { min, max }
   }
 
+  // Inside out, so nothing hits it and surrounding it with a box changes
+  // nothing
+  pub fn empty() -> Self {
+    Self::new(scalar(f64::INFINITY), scalar(f64::NEG_INFINITY))
+  }
+
+  pub fn centroid(&self) -> Vec3 {
+    scalar(0.5) * (self.min + self.max)
+  }
+
+  pub fn surface_area(&self) -> f64 {
+    let d = self.max - self.min;
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+  }
+
+  pub fn longest_axis(&self) -> usize {
+    let d = self.max - self.min;
+    if d.x > d.y && d.x > d.z {
+      0
+    } else if d.y > d.z {
+      1
+    } else {
+      2
+    }
+  }
+
   #[allow(dead_code)]
   pub fn slower_hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
     for a in 0..3 {
@@ -44,8 +72,9 @@ impl Aabb {
   }
 
   pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+    stats::count_box_tests(1);
     for a in 0..3 {
-      let inv_d = 1.0 / r.direction()[a];
+      let inv_d = r.inv_direction()[a];
       let mut t0 = (self.min[a] - r.origin()[a]) * inv_d;
       let mut t1 = (self.max[a] - r.origin()[a]) * inv_d;
       if inv_d < 0.0 {
@@ -93,141 +122,596 @@ pub fn surrounding_box(box0: Aabb, box1: Aabb) -> Aabb {
   return Aabb::new(small, big);
 }
 
+// Splits are only tried at the edges of this many buckets along each axis
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of checking a node's box, relative to testing one primitive
+const TRAVERSAL_COST: f64 = 0.125;
+// new_parallel() hands subtrees to other threads down to this many levels,
+// as long as they're big enough to be worth a thread
+const PARALLEL_DEPTH: usize = 3;
+const PARALLEL_MIN_ITEMS: usize = 4096;
+// Below this depth splits just halve, so even a lopsided tree stays shallow
+// enough for FlatBvh's fixed size stack
+const SAH_MAX_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+// A refit tree gets rebuilt once its SAH cost is this many times what it
+// was when it was built
+const REFIT_LIMIT: f64 = 1.5;
+
 #[derive(Debug)]
-pub struct BvhNode<'a> {
-  // Okay, this should either own it, or contain a specific item?
-  pub contents: Option<&'a dyn Hitable>,
-  pub left: Option<Box<BvhNode<'a>>>,
-  pub right: Option<Box<BvhNode<'a>>>,
+pub struct BvhNode {
+  // Leaves share ownership, so one prebuilt tree can sit behind many instances
+  pub contents: Vec<Arc<dyn Hitable>>,
+  pub left: Option<Box<BvhNode>>,
+  pub right: Option<Box<BvhNode>>,
   pub boxy: Aabb,
+  // What the children were split along, rays going up it meet left first
+  pub axis: usize,
+  // Only the root has any. Planes and the like have no box to put in the
+  // tree, so every ray tries them on the side.
+  pub unbounded: Vec<Arc<dyn Hitable>>,
+}
+
+// Box and centroid are looked at many times while building, so get them once
+struct BuildItem {
+  hitable: Arc<dyn Hitable>,
+  boxy: Aabb,
+  centroid: Vec3,
 }
 
-impl<'a> BvhNode<'a> {
-  pub fn new(l: &'a mut [&dyn Hitable], time0: f64, time1: f64) -> Self {
-    let axis = (3.0 * rand::thread_rng().gen::<f64>()) as isize;
-    l.sort_unstable_by(if axis == 0 {
-      box_x_compare
-    } else if axis == 1 {
-      box_y_compare
+impl BvhNode {
+  pub fn from_list(list: HitableList, time0: f64, time1: f64) -> Self {
+    let l = list
+      .list
+      .into_iter()
+      .map(Arc::from)
+      .collect::<Vec<Arc<dyn Hitable>>>();
+    Self::new(&l, time0, time1)
+  }
+
+  // Binned surface area heuristic, the same list always gives the same tree
+  pub fn new(l: &[Arc<dyn Hitable>], time0: f64, time1: f64) -> Self {
+    Self::build_from(l, time0, time1, 0)
+  }
+
+  // Same tree as new(), with the biggest subtrees built on their own threads
+  pub fn new_parallel(l: &[Arc<dyn Hitable>], time0: f64, time1: f64) -> Self {
+    Self::build_from(l, time0, time1, PARALLEL_DEPTH)
+  }
+
+  fn build_from(l: &[Arc<dyn Hitable>], time0: f64, time1: f64, parallel_depth: usize) -> Self {
+    assert!(!l.is_empty(), "No hitables in BvhNode::new()");
+    let mut items = vec![];
+    let mut unbounded = vec![];
+    for hitable in l {
+      match hitable.bounding_box(time0, time1) {
+        Some(boxy) => items.push(BuildItem {
+          hitable: Arc::clone(hitable),
+          boxy,
+          centroid: boxy.centroid(),
+        }),
+        None => unbounded.push(Arc::clone(hitable)),
+      }
+    }
+
+    let mut root = if items.is_empty() {
+      // Nothing for the tree, it gets a box no ray can go through
+      Self::leaf(&[], Aabb::empty())
     } else {
-      box_z_compare
-    });
+      Self::build(&mut items, 0, parallel_depth)
+    };
+    root.unbounded = unbounded;
+    root
+  }
+
+  fn build(items: &mut [BuildItem], depth: usize, parallel_depth: usize) -> Self {
+    let boxy = items[1..]
+      .iter()
+      .fold(items[0].boxy, |boxy, item| surrounding_box(boxy, item.boxy));
+    let centroids = items[1..].iter().fold(
+      Aabb::new(items[0].centroid, items[0].centroid),
+      |boxy, item| surrounding_box(boxy, Aabb::new(item.centroid, item.centroid)),
+    );
 
-    // should be possible to avoid cloning and just box the remains of the vec in place
-    if l.len() == 1 {
-      Self {
-        contents: Some(&*l[0]),
-        left: None,
-        right: None,
-        boxy: l[0]
-          .bounding_box(time0, time1)
-          .expect("No bounding box in BvhNode::new() 1"),
+    let split = if depth < SAH_MAX_DEPTH {
+      sah_split(items, &boxy, &centroids)
+    } else {
+      None
+    };
+    let (mid, axis) = match split {
+      Some((cost, _, _)) if items.len() <= MAX_LEAF_SIZE && cost >= items.len() as f64 => {
+        return Self::leaf(items, boxy);
       }
+      Some((_, axis, bin)) => {
+        let mid = partition(items, |item| {
+          bin_index(item.centroid[axis], &centroids, axis) < bin
+        });
+        (mid, axis)
+      }
+      None if items.len() <= MAX_LEAF_SIZE => return Self::leaf(items, boxy),
+      None => {
+        // Every centroid is in the same spot or the tree is too deep already,
+        // so just halve them
+        let axis = centroids.longest_axis();
+        items.sort_by(|a, b| box_compare(&a.boxy, &b.boxy, axis));
+        (items.len() / 2, axis)
+      }
+    };
+
+    let parallel = parallel_depth > 0 && items.len() >= PARALLEL_MIN_ITEMS;
+    let (left_items, right_items) = items.split_at_mut(mid);
+    let (left, right) = if parallel {
+      thread::scope(|scope| {
+        let left = scope.spawn(|| Self::build(left_items, depth + 1, parallel_depth - 1));
+        let right = Self::build(right_items, depth + 1, parallel_depth - 1);
+        (left.join().expect("BVH build thread panicked"), right)
+      })
     } else {
-      let (left_vec, right_vec) = l.split_at_mut(l.len() / 2);
-      let left = Box::new(BvhNode::new(left_vec, time0, time1));
-      let right = Box::new(BvhNode::new(right_vec, time0, time1));
+      (
+        Self::build(left_items, depth + 1, 0),
+        Self::build(right_items, depth + 1, 0),
+      )
+    };
 
-      let box_left = left.bounding_box(time0, time1);
-      let box_right = right.bounding_box(time0, time1);
+    Self {
+      contents: vec![],
+      left: Some(Box::new(left)),
+      right: Some(Box::new(right)),
+      boxy,
+      axis,
+      unbounded: vec![],
+    }
+  }
 
-      assert!(
-        box_left.is_some() && box_right.is_some(),
-        "No bounding box in BvhNode::new() 2"
-      );
+  fn leaf(items: &[BuildItem], boxy: Aabb) -> Self {
+    Self {
+      contents: items.iter().map(|item| Arc::clone(&item.hitable)).collect(),
+      left: None,
+      right: None,
+      boxy,
+      axis: 0,
+      unbounded: vec![],
+    }
+  }
+}
+
+fn bin_index(centroid: f64, centroids: &Aabb, axis: usize) -> usize {
+  let offset = (centroid - centroids.min[axis]) / (centroids.max[axis] - centroids.min[axis]);
+  ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+// Cheapest split as (cost, axis, first bin on the right), with the cost
+// counted in primitive tests so it compares directly to making a leaf
+fn sah_split(items: &[BuildItem], boxy: &Aabb, centroids: &Aabb) -> Option<(f64, usize, usize)> {
+  // A flat box still has to beat the leaf cost, so don't let it divide by zero
+  let area = boxy.surface_area().max(f64::MIN_POSITIVE);
+  let mut best: Option<(f64, usize, usize)> = None;
+
+  for axis in 0..3 {
+    if centroids.max[axis] <= centroids.min[axis] {
+      continue;
+    }
+    let mut counts = [0usize; SAH_BINS];
+    let mut bounds: [Option<Aabb>; SAH_BINS] = [None; SAH_BINS];
+    for item in items {
+      let bin = bin_index(item.centroid[axis], centroids, axis);
+      counts[bin] += 1;
+      bounds[bin] = Some(bounds[bin].map_or(item.boxy, |b| surrounding_box(b, item.boxy)));
+    }
+
+    // Sweep in from the right first so each split knows what's past it
+    let mut right_cost = [0.0; SAH_BINS];
+    let mut count = 0;
+    let mut right_box: Option<Aabb> = None;
+    for bin in (1..SAH_BINS).rev() {
+      count += counts[bin];
+      right_box = merge(right_box, bounds[bin]);
+      right_cost[bin] = count as f64 * right_box.map_or(0.0, |b| b.surface_area());
+    }
 
-      Self {
-        contents: None,
-        left: Some(left),
-        right: Some(right),
-        boxy: surrounding_box(box_left.unwrap(), box_right.unwrap()),
+    let mut count = 0;
+    let mut left_box: Option<Aabb> = None;
+    for bin in 1..SAH_BINS {
+      count += counts[bin - 1];
+      left_box = merge(left_box, bounds[bin - 1]);
+      if count == 0 || count == items.len() {
+        continue;
+      }
+      let left_cost = count as f64 * left_box.map_or(0.0, |b| b.surface_area());
+      let cost = TRAVERSAL_COST + (left_cost + right_cost[bin]) / area;
+      if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+        best = Some((cost, axis, bin));
       }
     }
   }
-}
 
-fn box_x_compare(a: &&dyn Hitable, b: &&dyn Hitable) -> Ordering {
-  let box_left = a.bounding_box(0.0, 0.0);
-  let box_right = b.bounding_box(0.0, 0.0);
+  best
+}
 
-  assert!(
-    box_left.is_some() && box_right.is_some(),
-    "No bounding box in BvhNode::new()"
-  );
+fn merge(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+  match (a, b) {
+    (Some(a), Some(b)) => Some(surrounding_box(a, b)),
+    (a, None) => a,
+    (None, b) => b,
+  }
+}
 
-  if box_left.unwrap().min.x - box_right.unwrap().min.x < 0.0 {
-    Ordering::Less
-  } else {
-    Ordering::Greater
+// Moves everything matching to the front and returns how many there were
+fn partition(items: &mut [BuildItem], is_left: impl Fn(&BuildItem) -> bool) -> usize {
+  let mut mid = 0;
+  for i in 0..items.len() {
+    if is_left(&items[i]) {
+      items.swap(i, mid);
+      mid += 1;
+    }
   }
+  mid
 }
 
-fn box_y_compare(a: &&dyn Hitable, b: &&dyn Hitable) -> Ordering {
-  let box_left = a.bounding_box(0.0, 0.0);
-  let box_right = b.bounding_box(0.0, 0.0);
+fn box_compare(a: &Aabb, b: &Aabb, axis: usize) -> Ordering {
+  a.min[axis]
+    .partial_cmp(&b.min[axis])
+    .unwrap_or(Ordering::Equal)
+}
 
-  assert!(
-    box_left.is_some() && box_right.is_some(),
-    "No bounding box in BvhNode::new()"
-  );
+impl Hitable for BvhNode {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    stats::count_node_visit();
+    let mut closest = t_max;
+    let mut result = None;
+    for hitable in self.unbounded.iter() {
+      stats::count_primitive_test();
+      if let Some(rec) = hitable.hit(r, t_min, closest) {
+        closest = rec.t;
+        result = Some(rec);
+      }
+    }
 
-  if box_left.unwrap().min.y - box_right.unwrap().min.y < 0.0 {
-    Ordering::Less
-  } else {
-    Ordering::Greater
+    if !self.boxy.hit(r, t_min, closest) {
+      return result;
+    }
+
+    for hitable in self.contents.iter() {
+      stats::count_primitive_test();
+      if let Some(rec) = hitable.hit(r, t_min, closest) {
+        closest = rec.t;
+        result = Some(rec);
+      }
+    }
+
+    // Whatever the left side hits, the right side only has to beat it
+    if let Some(rec) = self.left.as_ref().and_then(|h| h.hit(r, t_min, closest)) {
+      closest = rec.t;
+      result = Some(rec);
+    }
+    if let Some(rec) = self.right.as_ref().and_then(|h| h.hit(r, t_min, closest)) {
+      result = Some(rec);
+    }
+    result
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    stats::count_node_visit();
+    if self.unbounded.iter().any(|hitable| {
+      stats::count_primitive_test();
+      hitable.occluded(r, t_min, t_max)
+    }) {
+      return true;
+    }
+    if !self.boxy.hit(r, t_min, t_max) {
+      return false;
+    }
+    self.contents.iter().any(|hitable| {
+      stats::count_primitive_test();
+      hitable.occluded(r, t_min, t_max)
+    }) || self
+      .left
+      .as_ref()
+      .is_some_and(|h| h.occluded(r, t_min, t_max))
+      || self
+        .right
+        .as_ref()
+        .is_some_and(|h| h.occluded(r, t_min, t_max))
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    if self.unbounded.is_empty() {
+      Some(self.boxy)
+    } else {
+      None
+    }
   }
 }
 
-fn box_z_compare(a: &&dyn Hitable, b: &&dyn Hitable) -> Ordering {
-  let box_left = a.bounding_box(0.0, 0.0);
-  let box_right = b.bounding_box(0.0, 0.0);
+// BvhNode laid out depth first in one array. A node's first child is right
+// after it, so only the second child's index has to be stored.
+#[derive(Debug)]
+struct FlatNode {
+  boxy: Aabb,
+  // Leaves: where their hitables start. Others: index of the second child.
+  offset: u32,
+  // Zero for everything but leaves
+  count: u16,
+  axis: u8,
+}
 
-  assert!(
-    box_left.is_some() && box_right.is_some(),
-    "No bounding box in BvhNode::new()"
-  );
+#[derive(Debug)]
+pub struct FlatBvh {
+  nodes: Vec<FlatNode>,
+  hitables: Vec<Arc<dyn Hitable>>,
+  unbounded: Vec<Arc<dyn Hitable>>,
+  built_cost: f64,
+}
 
-  if box_left.unwrap().min.z - box_right.unwrap().min.z < 0.0 {
-    Ordering::Less
-  } else {
-    Ordering::Greater
+impl FlatBvh {
+  pub fn from_list(list: HitableList, time0: f64, time1: f64) -> Self {
+    Self::from_tree(BvhNode::from_list(list, time0, time1))
+  }
+
+  pub fn from_tree(mut tree: BvhNode) -> Self {
+    let mut flat = Self {
+      nodes: vec![],
+      hitables: vec![],
+      unbounded: std::mem::take(&mut tree.unbounded),
+      built_cost: 0.0,
+    };
+    flat.flatten(tree);
+    flat.built_cost = flat.sah_cost();
+    flat
+  }
+
+  fn flatten(&mut self, node: BvhNode) {
+    let index = self.nodes.len();
+    self.nodes.push(FlatNode {
+      boxy: node.boxy,
+      offset: self.hitables.len() as u32,
+      count: node.contents.len() as u16,
+      axis: node.axis as u8,
+    });
+    self.hitables.extend(node.contents);
+
+    if let (Some(left), Some(right)) = (node.left, node.right) {
+      self.flatten(*left);
+      self.nodes[index].offset = self.nodes.len() as u32;
+      self.flatten(*right);
+    }
+  }
+
+  // New boxes for the same tree, for when hitables have moved but none were
+  // added or removed. Children come after their parents, so going backwards
+  // every child is done before the node that holds it.
+  pub fn refit(&mut self, time0: f64, time1: f64) {
+    // Only unbounded hitables, the one node is an empty leaf
+    if self.hitables.is_empty() {
+      return;
+    }
+
+    for index in (0..self.nodes.len()).rev() {
+      let (offset, count) = (
+        self.nodes[index].offset as usize,
+        self.nodes[index].count as usize,
+      );
+      self.nodes[index].boxy = if count > 0 {
+        self.hitables[offset..offset + count]
+          .iter()
+          .map(|hitable| {
+            hitable
+              .bounding_box(time0, time1)
+              .expect("No bounding box in FlatBvh::refit()")
+          })
+          .reduce(surrounding_box)
+          .unwrap()
+      } else {
+        surrounding_box(self.nodes[index + 1].boxy, self.nodes[offset].boxy)
+      };
+    }
+
+    // Things that moved apart leave boxes overlapping that used to be tight
+    if self.sah_cost() > REFIT_LIMIT * self.built_cost {
+      let unbounded = std::mem::take(&mut self.unbounded);
+      *self = Self::from_tree(BvhNode::new_parallel(&self.hitables, time0, time1));
+      self.unbounded = unbounded;
+    }
+  }
+
+  pub fn stats(&self) -> TreeStats {
+    let mut stats = TreeStats {
+      nodes: self.nodes.len(),
+      hitables: self.hitables.len(),
+      unbounded: self.unbounded.len(),
+      sah_cost: self.sah_cost(),
+      bytes: self.nodes.len() * std::mem::size_of::<FlatNode>()
+        + (self.hitables.len() + self.unbounded.len()) * std::mem::size_of::<Arc<dyn Hitable>>(),
+      ..TreeStats::default()
+    };
+
+    // Same walk as hit(), going everywhere, with each node's depth alongside
+    let mut stack = vec![(0, 1)];
+    while let Some((index, depth)) = stack.pop() {
+      let node = &self.nodes[index];
+      stats.depth = stats.depth.max(depth);
+      // A tree of only unbounded hitables is one empty leaf
+      if node.count > 0 || self.hitables.is_empty() {
+        stats.leaves += 1;
+      } else {
+        stack.push((index + 1, depth + 1));
+        stack.push((node.offset as usize, depth + 1));
+      }
+    }
+    stats
+  }
+
+  // Expected primitive tests per ray, what the builder tries to keep down
+  fn sah_cost(&self) -> f64 {
+    let area = self.nodes[0].boxy.surface_area().max(f64::MIN_POSITIVE);
+    let total: f64 = self
+      .nodes
+      .iter()
+      .map(|node| {
+        let cost = if node.count > 0 {
+          node.count as f64
+        } else {
+          TRAVERSAL_COST
+        };
+        cost * node.boxy.surface_area()
+      })
+      .sum();
+    total / area
   }
 }
 
-impl Hitable for BvhNode<'_> {
+impl Hitable for FlatBvh {
   fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-    return if self.boxy.hit(r, t_min, t_max) {
-      if let Some(hit_content) = self.contents.and_then(|h| h.hit(r, t_min, t_max)) {
-        return Some(hit_content);
+    let mut closest = t_max;
+    let mut result = None;
+    for hitable in self.unbounded.iter() {
+      stats::count_primitive_test();
+      if let Some(rec) = hitable.hit(r, t_min, closest) {
+        closest = rec.t;
+        result = Some(rec);
       }
+    }
 
-      let hit_left: Option<HitRecord> = self
-        .left
-        .as_ref()
-        .and_then(|h: &Box<BvhNode>| h.hit(r, t_min, t_max));
-      let hit_right: Option<HitRecord> = self
-        .right
-        .as_ref()
-        .and_then(|h: &Box<BvhNode>| h.hit(r, t_min, t_max));
-      if hit_left.is_some() && hit_right.is_some() {
-        if hit_left.as_ref().unwrap().t < hit_right.as_ref().unwrap().t {
-          hit_left
+    let mut stack = [0u32; STACK_SIZE];
+    let mut top = 0;
+    let mut index = 0;
+
+    loop {
+      stats::count_node_visit();
+      let node = &self.nodes[index as usize];
+      // Only boxes that could still hold something closer get opened
+      if node.boxy.hit(r, t_min, closest) {
+        if node.count > 0 {
+          let start = node.offset as usize;
+          for hitable in &self.hitables[start..start + node.count as usize] {
+            stats::count_primitive_test();
+            if let Some(rec) = hitable.hit(r, t_min, closest) {
+              closest = rec.t;
+              result = Some(rec);
+            }
+          }
         } else {
-          hit_right
+          // Go into the child nearer the ray first and come back for the other
+          let (near, far) = if r.direction()[node.axis as usize] < 0.0 {
+            (node.offset, index + 1)
+          } else {
+            (index + 1, node.offset)
+          };
+          stack[top] = far;
+          top += 1;
+          index = near;
+          continue;
         }
-      } else if hit_left.is_some() {
-        hit_left
-      } else if hit_right.is_some() {
-        hit_right
-      } else {
-        None
       }
+
+      if top == 0 {
+        return result;
+      }
+      top -= 1;
+      index = stack[top];
+    }
+  }
+
+  // Same walk, but any hit will do, so there's no order to keep and t_max
+  // never shrinks
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    if self.unbounded.iter().any(|hitable| {
+      stats::count_primitive_test();
+      hitable.occluded(r, t_min, t_max)
+    }) {
+      return true;
+    }
+
+    let mut stack = [0u32; STACK_SIZE];
+    let mut top = 0;
+    let mut index = 0;
+
+    loop {
+      stats::count_node_visit();
+      let node = &self.nodes[index as usize];
+      if node.boxy.hit(r, t_min, t_max) {
+        if node.count > 0 {
+          let start = node.offset as usize;
+          let hitables = &self.hitables[start..start + node.count as usize];
+          if hitables.iter().any(|hitable| {
+            stats::count_primitive_test();
+            hitable.occluded(r, t_min, t_max)
+          }) {
+            return true;
+          }
+        } else {
+          stack[top] = node.offset;
+          top += 1;
+          index += 1;
+          continue;
+        }
+      }
+
+      if top == 0 {
+        return false;
+      }
+      top -= 1;
+      index = stack[top];
+    }
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    if self.unbounded.is_empty() {
+      Some(self.nodes[0].boxy)
     } else {
       None
-    };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::*;
+  use rand::prelude::*;
+
+  fn random_vec3(rng: &mut StdRng, size: f64) -> Vec3 {
+    vec3(
+      rng.gen_range(-size, size),
+      rng.gen_range(-size, size),
+      rng.gen_range(-size, size),
+    )
   }
 
-  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
-    Some(self.boxy)
+  #[test]
+  fn flat_bvh_agrees_with_the_tree() {
+    let mut rng = StdRng::seed_from_u64(43);
+    let mut world: Vec<Arc<dyn Hitable>> = vec![];
+    for _ in 0..200 {
+      let center = random_vec3(&mut rng, 10.0);
+      let radius = rng.gen_range(0.1, 1.0);
+      let material = Lambertian::new_from_color(scalar(0.5));
+      world.push(Arc::new(Sphere::new(center, radius, material)));
+    }
+    // Unbounded ones sit outside the tree in both
+    let floor = Plane::new(
+      vec3(0.0, -10.0, 0.0),
+      vec3(0.0, 1.0, 0.0),
+      Lambertian::new_from_color(scalar(0.5)),
+    );
+    world.push(Arc::new(floor));
+
+    let tree = BvhNode::new(&world, 0.0, 1.0);
+    let flat = FlatBvh::from_tree(BvhNode::new(&world, 0.0, 1.0));
+    for _ in 0..10000 {
+      let r = Ray::new(random_vec3(&mut rng, 15.0), random_vec3(&mut rng, 1.0));
+      let t_max = if rng.gen::<f64>() < 0.5 {
+        f64::MAX
+      } else {
+        rng.gen_range(0.0, 30.0)
+      };
+      let expected = tree.hit(&r, 0.001, t_max).map(|rec| (rec.t, rec.p));
+      assert_eq!(
+        flat.hit(&r, 0.001, t_max).map(|rec| (rec.t, rec.p)),
+        expected
+      );
+      assert_eq!(flat.occluded(&r, 0.001, t_max), expected.is_some());
+    }
   }
 }
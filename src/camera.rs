@@ -11,9 +11,13 @@ pub struct Camera {
   pub v: Vec3,
   pub w: Vec3,
   pub lens_radius: f64,
+  // Shutter open and close, rays are spread uniformly between them
+  pub time0: f64,
+  pub time1: f64,
 }
 
 impl Camera {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     lookfrom: Vec3,
     lookat: Vec3,
@@ -22,6 +26,8 @@ impl Camera {
     aspect: f64,
     aperture: f64,
     focus_dist: f64,
+    time0: f64,
+    time1: f64,
   ) -> Self {
     let lens_radius = aperture / 2.0;
     // vfov is top to bottom in degrees
@@ -47,17 +53,30 @@ impl Camera {
       v: v,
       w: w,
       lens_radius: lens_radius,
+      time0,
+      time1,
+    }
+  }
+
+  // Same view with the shutter open from `time` for `shutter` seconds
+  pub fn with_shutter(&self, time: f64, shutter: f64) -> Self {
+    Self {
+      time0: time,
+      time1: time + shutter,
+      ..*self
     }
   }
 
   pub fn get_ray(&self, s: f64, t: f64) -> Ray {
     let rd = scalar(self.lens_radius) * random_in_unit_disk();
     let offset = self.u * scalar(rd.x) + self.v * scalar(rd.y);
-    Ray::new(
+    let time = self.time0 + rand::thread_rng().gen::<f64>() * (self.time1 - self.time0);
+    Ray::new_at_time(
       self.origin + offset,
       self.lower_left_corner + scalar(s) * self.horizontal + scalar(t) * self.vertical
         - self.origin
         - offset,
+      time,
     )
   }
 }
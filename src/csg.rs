@@ -0,0 +1,219 @@
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::ray::Ray;
+
+#[derive(Debug, Clone, Copy)]
+pub enum CsgOp {
+  Union,
+  Intersection,
+  // Left with right carved out of it
+  Difference,
+}
+
+impl CsgOp {
+  fn inside(self, in_left: bool, in_right: bool) -> bool {
+    match self {
+      CsgOp::Union => in_left || in_right,
+      CsgOp::Intersection => in_left && in_right,
+      CsgOp::Difference => in_left && !in_right,
+    }
+  }
+}
+
+// Both children have to be closed, anything open has no inside to combine
+#[derive(Debug)]
+pub struct Csg {
+  left: Box<dyn Hitable>,
+  right: Box<dyn Hitable>,
+  op: CsgOp,
+}
+
+impl Csg {
+  pub fn new(left: Box<dyn Hitable>, right: Box<dyn Hitable>, op: CsgOp) -> Self {
+    Self { left, right, op }
+  }
+
+  pub fn union(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Self {
+    Self::new(left, right, CsgOp::Union)
+  }
+
+  pub fn intersection(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Self {
+    Self::new(left, right, CsgOp::Intersection)
+  }
+
+  pub fn difference(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Self {
+    Self::new(left, right, CsgOp::Difference)
+  }
+}
+
+struct Boundary<'a> {
+  rec: HitRecord<'a>,
+  from_left: bool,
+  entering: bool,
+}
+
+impl Hitable for Csg {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    for (entry, exit) in self.intervals(r) {
+      if entry.t > t_max {
+        break;
+      }
+      if entry.t > t_min {
+        return Some(entry);
+      }
+      if exit.t > t_min && exit.t < t_max {
+        return Some(exit);
+      }
+    }
+    None
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    match self.op {
+      CsgOp::Union => Some(surrounding_box(
+        self.left.bounding_box(t0, t1)?,
+        self.right.bounding_box(t0, t1)?,
+      )),
+      CsgOp::Intersection => {
+        let left = self.left.bounding_box(t0, t1)?;
+        let right = self.right.bounding_box(t0, t1)?;
+        let mut min = left.min;
+        let mut max = left.max;
+        for c in 0..3 {
+          min[c] = min[c].max(right.min[c]);
+          max[c] = max[c].min(right.max[c]).max(min[c]);
+        }
+        Some(Aabb::new(min, max))
+      }
+      CsgOp::Difference => self.left.bounding_box(t0, t1),
+    }
+  }
+
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    let mut boundaries = vec![];
+    for (from_left, intervals) in [
+      (true, self.left.intervals(r)),
+      (false, self.right.intervals(r)),
+    ] {
+      for (entry, exit) in intervals {
+        boundaries.push(Boundary {
+          rec: entry,
+          from_left,
+          entering: true,
+        });
+        boundaries.push(Boundary {
+          rec: exit,
+          from_left,
+          entering: false,
+        });
+      }
+    }
+    boundaries.sort_by(|a, b| a.rec.t.total_cmp(&b.rec.t));
+
+    let mut result = vec![];
+    let mut in_left = false;
+    let mut in_right = false;
+    let mut start = None;
+    for boundary in boundaries {
+      let was_inside = self.op.inside(in_left, in_right);
+      if boundary.from_left {
+        in_left = boundary.entering;
+      } else {
+        in_right = boundary.entering;
+      }
+      let is_inside = self.op.inside(in_left, in_right);
+
+      let mut rec = boundary.rec;
+      if let (CsgOp::Difference, false) = (self.op, boundary.from_left) {
+        // The carved out surface faces into what used to be the right's inside
+        rec.normal = -rec.normal;
+        rec.front_face = !rec.front_face;
+      }
+      if !was_inside && is_inside {
+        start = Some(rec);
+      } else if was_inside && !is_inside {
+        if let Some(entry) = start.take() {
+          result.push((entry, rec));
+        }
+      }
+    }
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::*;
+  use crate::vec3::*;
+
+  fn sphere(x: f64) -> Box<dyn Hitable> {
+    Box::new(Sphere::new(
+      vec3(x, 0.0, 0.0),
+      1.0,
+      Lambertian::new_from_color(scalar(0.5)),
+    ))
+  }
+
+  // Spheres over [-1.5, 0.5] and [-0.5, 1.5] on x, seen from x = -10
+  fn spans(csg: &Csg) -> Vec<(f64, f64)> {
+    let r = Ray::new(vec3(-10.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+    csg
+      .intervals(&r)
+      .iter()
+      .map(|(entry, exit)| (entry.t, exit.t))
+      .collect()
+  }
+
+  fn assert_spans(got: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+    assert_eq!(got.len(), expected.len(), "spans {:?}", got);
+    for (a, b) in got.iter().zip(expected) {
+      assert!(
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9,
+        "spans {:?}",
+        got
+      );
+    }
+  }
+
+  #[test]
+  fn union_joins_overlaps() {
+    assert_spans(
+      spans(&Csg::union(sphere(-0.5), sphere(0.5))),
+      &[(8.5, 11.5)],
+    );
+    // Apart they stay two pieces
+    assert_spans(
+      spans(&Csg::union(sphere(-2.0), sphere(2.0))),
+      &[(7.0, 9.0), (11.0, 13.0)],
+    );
+  }
+
+  #[test]
+  fn intersection_keeps_the_overlap() {
+    assert_spans(
+      spans(&Csg::intersection(sphere(-0.5), sphere(0.5))),
+      &[(9.5, 10.5)],
+    );
+    assert_spans(spans(&Csg::intersection(sphere(-2.0), sphere(2.0))), &[]);
+  }
+
+  #[test]
+  fn difference_carves_out_the_right() {
+    let csg = Csg::difference(sphere(-0.5), sphere(0.5));
+    assert_spans(spans(&csg), &[(8.5, 9.5)]);
+    // The exit is on the right sphere, turned to face out of what's left
+    let r = Ray::new(vec3(-10.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+    assert!(csg.intervals(&r)[0].1.normal.x > 0.0);
+    // Carving from the middle leaves two pieces
+    let hollow = Csg::difference(
+      Box::new(Sphere::new(
+        scalar(0.0),
+        2.0,
+        Lambertian::new_from_color(scalar(0.5)),
+      )),
+      sphere(0.0),
+    );
+    assert_spans(spans(&hollow), &[(8.0, 9.0), (11.0, 12.0)]);
+  }
+}
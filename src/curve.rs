@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::onb::ONB;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+const MAX_DEPTH: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveType {
+  // Flat strip that turns to face every ray, what hair and fur want
+  Ribbon,
+  // Same strip, but the normal bends across it like a tube
+  Cylinder,
+}
+
+#[derive(Debug)]
+struct CurveCommon {
+  points: [Vec3; 4],
+  width0: f64,
+  width1: f64,
+  kind: CurveType,
+  material: Material,
+}
+
+impl CurveCommon {
+  fn width(&self, u: f64) -> f64 {
+    self.width0 + u * (self.width1 - self.width0)
+  }
+}
+
+// A piece of a cubic Bezier curve whose width goes linearly from width0 at
+// the start to width1 at the end. Intersection follows pbrt: the control
+// points go into a space where the ray runs down +z, then the curve is split
+// until each piece is close enough to a straight line to test directly.
+#[derive(Debug)]
+pub struct Curve {
+  common: Arc<CurveCommon>,
+  u_min: f64,
+  u_max: f64,
+  // Control points for just this piece, so its box fits tightly
+  points: [Vec3; 4],
+  boxy: Aabb,
+  max_depth: usize,
+}
+
+impl Curve {
+  // A long curve in one box would be tested by nearly every ray near it, so
+  // it's cut into `segments` pieces for the BVH to sort out
+  pub fn split(
+    points: [Vec3; 4],
+    width0: f64,
+    width1: f64,
+    kind: CurveType,
+    material: Material,
+    segments: usize,
+  ) -> Vec<Box<dyn Hitable>> {
+    let common = Arc::new(CurveCommon {
+      points,
+      width0,
+      width1,
+      kind,
+      material,
+    });
+    (0..segments)
+      .map(|i| {
+        let u_min = i as f64 / segments as f64;
+        let u_max = (i + 1) as f64 / segments as f64;
+        Box::new(Self::new_segment(Arc::clone(&common), u_min, u_max)) as Box<dyn Hitable>
+      })
+      .collect()
+  }
+
+  fn new_segment(common: Arc<CurveCommon>, u_min: f64, u_max: f64) -> Self {
+    let cp = &common.points;
+    let points = [
+      blossom(cp, u_min, u_min, u_min),
+      blossom(cp, u_min, u_min, u_max),
+      blossom(cp, u_min, u_max, u_max),
+      blossom(cp, u_max, u_max, u_max),
+    ];
+    let half_width = 0.5 * common.width(u_min).max(common.width(u_max));
+
+    let mut min = points[0];
+    let mut max = points[0];
+    for point in points.iter() {
+      for c in 0..3 {
+        min[c] = min[c].min(point[c]);
+        max[c] = max[c].max(point[c]);
+      }
+    }
+
+    // Enough splits that the straight pieces are within 5% of the width of
+    // the real curve. pbrt does this per ray in ray space, once here is close.
+    let mut bend: f64 = 0.0;
+    for w in points.windows(3) {
+      let second = w[0] - scalar(2.0) * w[1] + w[2];
+      bend = bend
+        .max(second.x.abs())
+        .max(second.y.abs())
+        .max(second.z.abs());
+    }
+    let epsilon = 0.1 * half_width;
+    let max_depth = if bend > 0.0 && epsilon > 0.0 {
+      ((2.0f64.sqrt() * 6.0 * bend / (8.0 * epsilon)).log2() * 0.5)
+        .round()
+        .clamp(0.0, MAX_DEPTH) as usize
+    } else {
+      0
+    };
+
+    Self {
+      boxy: Aabb::new(min - scalar(half_width), max + scalar(half_width)),
+      common,
+      u_min,
+      u_max,
+      points,
+      max_depth,
+    }
+  }
+
+  // Control points moved so the ray starts at the origin and runs down +z
+  fn ray_space_points(&self, r: &Ray) -> [Vec3; 4] {
+    let frame = ONB::build_from_w(r.direction());
+    let to_ray_space = |p: Vec3| {
+      let offset = p - r.origin();
+      vec3(
+        offset.dot(frame.u),
+        offset.dot(frame.v),
+        offset.dot(frame.w),
+      )
+    };
+    [
+      to_ray_space(self.points[0]),
+      to_ray_space(self.points[1]),
+      to_ray_space(self.points[2]),
+      to_ray_space(self.points[3]),
+    ]
+  }
+
+  // Nearest crossing of a piece already in ray space, as (distance, u).
+  // z_max shrinks as hits are found so later pieces only count if closer,
+  // and with `any_hit` the first one found is returned straight away.
+  #[allow(clippy::too_many_arguments)]
+  fn recursive_hit(
+    &self,
+    cp: &[Vec3; 4],
+    u0: f64,
+    u1: f64,
+    depth: usize,
+    z_min: f64,
+    z_max: &mut f64,
+    any_hit: bool,
+  ) -> Option<(f64, f64)> {
+    let half_width = 0.5 * self.common.width(u0).max(self.common.width(u1));
+    for c in 0..3 {
+      let low = cp.iter().fold(f64::INFINITY, |low, p| low.min(p[c]));
+      let high = cp.iter().fold(f64::NEG_INFINITY, |high, p| high.max(p[c]));
+      // The ray is the z axis, so x and y have to straddle 0
+      let (lo, hi) = if c == 2 { (z_min, *z_max) } else { (0.0, 0.0) };
+      if high + half_width < lo || low - half_width > hi {
+        return None;
+      }
+    }
+
+    if depth > 0 {
+      let (first, second) = split_bezier(cp);
+      let u_mid = 0.5 * (u0 + u1);
+      let near = self.recursive_hit(&first, u0, u_mid, depth - 1, z_min, z_max, any_hit);
+      if any_hit && near.is_some() {
+        return near;
+      }
+      let far = self.recursive_hit(&second, u_mid, u1, depth - 1, z_min, z_max, any_hit);
+      return far.or(near);
+    }
+
+    // The ray has to pass between the perpendiculars at each end
+    let start_edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+    let end_edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+    if start_edge < 0.0 || end_edge < 0.0 {
+      return None;
+    }
+
+    // Closest point to the ray along the straight line from end to end
+    let segment = cp[3] - cp[0];
+    let denom = segment.x * segment.x + segment.y * segment.y;
+    if denom == 0.0 {
+      return None;
+    }
+    let w = (-cp[0].x * segment.x - cp[0].y * segment.y) / denom;
+    let u = (u0 + w * (u1 - u0)).clamp(u0, u1);
+    let width = self.common.width(u);
+    let (pc, _) = eval_bezier(cp, w.clamp(0.0, 1.0));
+    if pc.x * pc.x + pc.y * pc.y > 0.25 * width * width {
+      return None;
+    }
+    if pc.z <= z_min || pc.z >= *z_max {
+      return None;
+    }
+    // Rays scattered off a fiber start inside it and shouldn't find the same
+    // fiber again. From anywhere outside, every point along the middle is
+    // more than half the width away, so no real hit is lost however close.
+    if pc.length() <= 0.5 * width {
+      return None;
+    }
+
+    *z_max = pc.z;
+    Some((pc.z, u))
+  }
+}
+
+impl Hitable for Curve {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let length = r.direction().length();
+    let mut z_max = t_max * length;
+    let (z, u) = self.recursive_hit(
+      &self.ray_space_points(r),
+      self.u_min,
+      self.u_max,
+      self.max_depth,
+      t_min * length,
+      &mut z_max,
+      false,
+    )?;
+
+    let t = z / length;
+    let p = r.point_at_parameter(t);
+    let (center, derivative) = eval_bezier(&self.common.points, u);
+    let tangent = derivative.unit();
+
+    // Facing the ray, then h is how far across the width the hit is
+    let d = r.direction() / scalar(length);
+    let facing = -(d - tangent * scalar(d.dot(tangent))).unit();
+    let across = facing.cross(tangent);
+    let width = self.common.width(u);
+    let h = ((p - center).dot(across) / (0.5 * width)).clamp(-1.0, 1.0);
+
+    let normal = match self.common.kind {
+      CurveType::Ribbon => facing,
+      CurveType::Cylinder => facing * scalar((1.0 - h * h).sqrt()) + across * scalar(h),
+    };
+
+    Some(HitRecord {
+      t,
+      u,
+      v: 0.5 + 0.5 * h,
+      p,
+      normal,
+      front_face: true,
+      tangent,
+      material: Some(&self.common.material),
+    })
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    let length = r.direction().length();
+    let mut z_max = t_max * length;
+    self
+      .recursive_hit(
+        &self.ray_space_points(r),
+        self.u_min,
+        self.u_max,
+        self.max_depth,
+        t_min * length,
+        &mut z_max,
+        true,
+      )
+      .is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(self.boxy)
+  }
+}
+
+fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+  a + scalar(t) * (b - a)
+}
+
+// Point on the curve for parameters (u0, u1, u2), picking them all the same
+// gives the curve itself and mixing two gives the control points of a piece
+fn blossom(cp: &[Vec3; 4], u0: f64, u1: f64, u2: f64) -> Vec3 {
+  let a = [
+    lerp(u0, cp[0], cp[1]),
+    lerp(u0, cp[1], cp[2]),
+    lerp(u0, cp[2], cp[3]),
+  ];
+  let b = [lerp(u1, a[0], a[1]), lerp(u1, a[1], a[2])];
+  lerp(u2, b[0], b[1])
+}
+
+fn split_bezier(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+  let a = [
+    lerp(0.5, cp[0], cp[1]),
+    lerp(0.5, cp[1], cp[2]),
+    lerp(0.5, cp[2], cp[3]),
+  ];
+  let b = [lerp(0.5, a[0], a[1]), lerp(0.5, a[1], a[2])];
+  let mid = lerp(0.5, b[0], b[1]);
+  ([cp[0], a[0], b[0], mid], [mid, b[1], a[2], cp[3]])
+}
+
+// Point and derivative at u
+fn eval_bezier(cp: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+  let a = [
+    lerp(u, cp[0], cp[1]),
+    lerp(u, cp[1], cp[2]),
+    lerp(u, cp[2], cp[3]),
+  ];
+  let b = [lerp(u, a[0], a[1]), lerp(u, a[1], a[2])];
+  let derivative = if (b[1] - b[0]).squared_length() > 0.0 {
+    scalar(3.0) * (b[1] - b[0])
+  } else {
+    // Doubled up control points at an end
+    cp[3] - cp[0]
+  };
+  (lerp(u, b[0], b[1]), derivative)
+}
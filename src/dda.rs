@@ -0,0 +1,55 @@
+// Step direction, t of the first cell boundary crossed, and t between
+// boundaries for one axis of a walk through a regular grid of cells,
+// shared by Grid and Heightfield
+pub fn dda_axis(
+  origin: f64,
+  direction: f64,
+  corner: f64,
+  size: f64,
+  cell: usize,
+) -> (isize, f64, f64) {
+  // Flat along this axis, so there's one cell and no boundary to cross
+  if size == 0.0 {
+    return (0, f64::INFINITY, f64::INFINITY);
+  }
+  if direction > 0.0 {
+    let boundary = corner + (cell + 1) as f64 * size;
+    (1, (boundary - origin) / direction, size / direction)
+  } else if direction < 0.0 {
+    let boundary = corner + cell as f64 * size;
+    (-1, (boundary - origin) / direction, -size / direction)
+  } else {
+    (0, f64::INFINITY, f64::INFINITY)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn zero_extent_axis_never_steps() {
+    // A flat grid has size 0 on that axis, whichever way the ray goes
+    for direction in [-1.0, 0.0, 1.0] {
+      assert_eq!(
+        dda_axis(0.5, direction, 0.0, 0.0, 0),
+        (0, f64::INFINITY, f64::INFINITY)
+      );
+    }
+  }
+
+  #[test]
+  fn parallel_axis_never_steps() {
+    assert_eq!(
+      dda_axis(0.5, 0.0, 0.0, 1.0, 0),
+      (0, f64::INFINITY, f64::INFINITY)
+    );
+  }
+
+  #[test]
+  fn steps_towards_the_next_boundary() {
+    // Cells of 2 from -4, starting at 1 in cell 2
+    assert_eq!(dda_axis(1.0, 0.5, -4.0, 2.0, 2), (1, 2.0, 4.0));
+    assert_eq!(dda_axis(1.0, -0.5, -4.0, 2.0, 2), (-1, 2.0, 4.0));
+  }
+}
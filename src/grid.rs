@@ -0,0 +1,274 @@
+use std::sync::Arc;
+
+use crate::bvh::*;
+use crate::dda::dda_axis;
+use crate::hitable::*;
+use crate::ray::Ray;
+use crate::stats;
+use crate::vec3::*;
+
+// Cells along the widest axis per cube root of the hitable count, pbrt's
+// GridAccel uses the same
+const DENSITY: f64 = 3.0;
+const MAX_RESOLUTION: usize = 128;
+
+// Uniform grid over the bounding boxes. Every cell lists the hitables whose
+// boxes overlap it, and rays walk the cells they cross in order, so for lots
+// of similar sized things it finds the nearest without a tree to go down.
+#[derive(Debug)]
+pub struct Grid {
+  hitables: Vec<Arc<dyn Hitable>>,
+  // No box to put in any cell, every ray tries these
+  unbounded: Vec<Arc<dyn Hitable>>,
+  // Cell i's hitables are indices[cell_starts[i]..cell_starts[i + 1]]
+  cell_starts: Vec<u32>,
+  indices: Vec<u32>,
+  resolution: [usize; 3],
+  cell_size: Vec3,
+  boxy: Aabb,
+}
+
+impl Grid {
+  pub fn from_list(list: HitableList, time0: f64, time1: f64) -> Self {
+    let l = list
+      .list
+      .into_iter()
+      .map(Arc::from)
+      .collect::<Vec<Arc<dyn Hitable>>>();
+    Self::new(&l, time0, time1)
+  }
+
+  pub fn new(l: &[Arc<dyn Hitable>], time0: f64, time1: f64) -> Self {
+    assert!(!l.is_empty(), "No hitables in Grid::new()");
+    let mut hitables = vec![];
+    let mut boxes = vec![];
+    let mut unbounded = vec![];
+    for hitable in l {
+      match hitable.bounding_box(time0, time1) {
+        Some(boxy) => {
+          hitables.push(Arc::clone(hitable));
+          boxes.push(boxy);
+        }
+        None => unbounded.push(Arc::clone(hitable)),
+      }
+    }
+
+    let boxy = boxes
+      .iter()
+      .cloned()
+      .reduce(surrounding_box)
+      .unwrap_or_else(Aabb::empty);
+    let extent = if boxes.is_empty() {
+      scalar(0.0)
+    } else {
+      boxy.max - boxy.min
+    };
+    let widest = extent.x.max(extent.y).max(extent.z);
+    let cells_per_unit = if widest > 0.0 {
+      DENSITY * (boxes.len() as f64).cbrt() / widest
+    } else {
+      0.0
+    };
+    let mut resolution = [1; 3];
+    for (a, cells) in resolution.iter_mut().enumerate() {
+      *cells = ((extent[a] * cells_per_unit).round() as usize).clamp(1, MAX_RESOLUTION);
+    }
+
+    let mut grid = Self {
+      hitables,
+      unbounded,
+      cell_starts: vec![],
+      indices: vec![],
+      resolution,
+      cell_size: vec3(
+        extent.x / resolution[0] as f64,
+        extent.y / resolution[1] as f64,
+        extent.z / resolution[2] as f64,
+      ),
+      boxy,
+    };
+    grid.fill(&boxes);
+    grid
+  }
+
+  // Counts what goes in each cell first so every list can share one array
+  fn fill(&mut self, boxes: &[Aabb]) {
+    let cell_count = self.resolution.iter().product::<usize>();
+    let ranges = boxes
+      .iter()
+      .map(|boxy| (self.cell_of(boxy.min), self.cell_of(boxy.max)))
+      .collect::<Vec<_>>();
+
+    let mut counts = vec![0u32; cell_count + 1];
+    for (lo, hi) in ranges.iter() {
+      self.for_cells(*lo, *hi, |cell| counts[cell + 1] += 1);
+    }
+    for cell in 0..cell_count {
+      counts[cell + 1] += counts[cell];
+    }
+
+    let mut next = counts.clone();
+    let mut indices = vec![0u32; counts[cell_count] as usize];
+    for (index, (lo, hi)) in ranges.iter().enumerate() {
+      self.for_cells(*lo, *hi, |cell| {
+        indices[next[cell] as usize] = index as u32;
+        next[cell] += 1;
+      });
+    }
+
+    self.cell_starts = counts;
+    self.indices = indices;
+  }
+
+  fn for_cells(&self, lo: [usize; 3], hi: [usize; 3], mut f: impl FnMut(usize)) {
+    for z in lo[2]..=hi[2] {
+      for y in lo[1]..=hi[1] {
+        for x in lo[0]..=hi[0] {
+          f(self.cell_index([x, y, z]));
+        }
+      }
+    }
+  }
+
+  // Clamped, so points on or just past the edge still land in a cell
+  fn cell_of(&self, p: Vec3) -> [usize; 3] {
+    let mut cell = [0; 3];
+    for (a, c) in cell.iter_mut().enumerate() {
+      let offset = ((p[a] - self.boxy.min[a]) / self.cell_size[a]).floor();
+      *c = (offset.max(0.0) as usize).min(self.resolution[a] - 1);
+    }
+    cell
+  }
+
+  fn cell_index(&self, cell: [usize; 3]) -> usize {
+    (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+  }
+
+  fn cell_hitables(&self, cell: [usize; 3]) -> &[u32] {
+    let index = self.cell_index(cell);
+    &self.indices[self.cell_starts[index] as usize..self.cell_starts[index + 1] as usize]
+  }
+
+  // Where the ray is inside the grid, clipped to [t_min, t_max]
+  fn box_span(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+    stats::count_box_tests(1);
+    for a in 0..3 {
+      let inv_d = r.inv_direction()[a];
+      let mut t0 = (self.boxy.min[a] - r.origin()[a]) * inv_d;
+      let mut t1 = (self.boxy.max[a] - r.origin()[a]) * inv_d;
+      if inv_d < 0.0 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+      t_min = t_min.max(t0);
+      t_max = t_max.min(t1);
+      if t_max < t_min {
+        return None;
+      }
+    }
+    Some((t_min, t_max))
+  }
+
+  // Calls `visit` with each cell the ray crosses between t_min and t_max and
+  // the t it leaves that cell at, nearest first, until `visit` returns true
+  fn walk(&self, r: &Ray, t_min: f64, t_max: f64, mut visit: impl FnMut(&[u32], f64) -> bool) {
+    let Some((t_enter, t_exit)) = self.box_span(r, t_min, t_max) else {
+      return;
+    };
+    let mut cell = self.cell_of(r.point_at_parameter(t_enter));
+
+    // 3D DDA, t_next is where the ray leaves the current slab of cells along
+    // each axis
+    let mut step = [0isize; 3];
+    let mut t_next = [0.0; 3];
+    let mut t_delta = [0.0; 3];
+    for a in 0..3 {
+      (step[a], t_next[a], t_delta[a]) = dda_axis(
+        r.origin()[a],
+        r.direction()[a],
+        self.boxy.min[a],
+        self.cell_size[a],
+        cell[a],
+      );
+    }
+
+    loop {
+      let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+        0
+      } else if t_next[1] < t_next[2] {
+        1
+      } else {
+        2
+      };
+      if visit(self.cell_hitables(cell), t_next[axis].min(t_exit)) {
+        return;
+      }
+
+      if t_next[axis] >= t_exit {
+        return;
+      }
+      let next = cell[axis] as isize + step[axis];
+      if next < 0 || next >= self.resolution[axis] as isize {
+        return;
+      }
+      cell[axis] = next as usize;
+      t_next[axis] += t_delta[axis];
+    }
+  }
+}
+
+impl Hitable for Grid {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut closest = t_max;
+    let mut result = None;
+    for hitable in self.unbounded.iter() {
+      stats::count_primitive_test();
+      if let Some(rec) = hitable.hit(r, t_min, closest) {
+        closest = rec.t;
+        result = Some(rec);
+      }
+    }
+
+    self.walk(r, t_min, closest, |indices, t_cell_exit| {
+      stats::count_node_visit();
+      for &index in indices {
+        stats::count_primitive_test();
+        if let Some(rec) = self.hitables[index as usize].hit(r, t_min, closest) {
+          closest = rec.t;
+          result = Some(rec);
+        }
+      }
+      // Something big can be hit past this cell, and a later cell can still
+      // hold a nearer hit
+      closest <= t_cell_exit
+    });
+    result
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    if self.unbounded.iter().any(|hitable| {
+      stats::count_primitive_test();
+      hitable.occluded(r, t_min, t_max)
+    }) {
+      return true;
+    }
+
+    let mut occluded = false;
+    self.walk(r, t_min, t_max, |indices, _| {
+      stats::count_node_visit();
+      occluded = indices.iter().any(|&index| {
+        stats::count_primitive_test();
+        self.hitables[index as usize].occluded(r, t_min, t_max)
+      });
+      occluded
+    });
+    occluded
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    if self.unbounded.is_empty() {
+      Some(self.boxy)
+    } else {
+      None
+    }
+  }
+}
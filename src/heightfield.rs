@@ -0,0 +1,306 @@
+use crate::bvh::*;
+use crate::dda::dda_axis;
+use crate::hitable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+// A grid of heights over the xz plane, each cell split into two triangles.
+// Rays walk the cells they cross in order, so the first cell with a hit has
+// the nearest one and the rest of the grid is never looked at.
+#[derive(Debug)]
+pub struct Heightfield {
+  nx: usize,
+  nz: usize,
+  // World space height of each sample, row by row along z
+  heights: Vec<f64>,
+  normals: Vec<Vec3>,
+  corner: Vec3,
+  cell_x: f64,
+  cell_z: f64,
+  boxy: Aabb,
+  pub material: Material,
+}
+
+impl Heightfield {
+  // `heights` are above corner.y, `size` is the x and z extent of the grid
+  pub fn new(
+    heights: Vec<f64>,
+    nx: usize,
+    nz: usize,
+    corner: Vec3,
+    size_x: f64,
+    size_z: f64,
+    material: Material,
+  ) -> Self {
+    assert!(nx >= 2 && nz >= 2, "Heightfield needs at least 2x2 samples");
+    assert_eq!(heights.len(), nx * nz, "Heightfield sample count mismatch");
+
+    let heights: Vec<f64> = heights.into_iter().map(|h| corner.y + h).collect();
+    let min_y = heights.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_y = heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    let mut field = Self {
+      nx,
+      nz,
+      heights,
+      normals: vec![],
+      corner,
+      cell_x: size_x / (nx - 1) as f64,
+      cell_z: size_z / (nz - 1) as f64,
+      boxy: Aabb::new(
+        vec3(corner.x, min_y - 0.0001, corner.z),
+        vec3(corner.x + size_x, max_y + 0.0001, corner.z + size_z),
+      ),
+      material,
+    };
+    field.normals = field.vertex_normals();
+    field
+  }
+
+  // `height(u, v)` gets the position across the grid in [0, 1] and its
+  // result is scaled by size.y, e.g. |u, v| perlin.turb(vec3(u, 0.0, v), 7)
+  pub fn from_fn(
+    nx: usize,
+    nz: usize,
+    corner: Vec3,
+    size: Vec3,
+    height: impl Fn(f64, f64) -> f64,
+    material: Material,
+  ) -> Self {
+    let mut heights = Vec::with_capacity(nx * nz);
+    for j in 0..nz {
+      for i in 0..nx {
+        let u = i as f64 / (nx - 1) as f64;
+        let v = j as f64 / (nz - 1) as f64;
+        heights.push(size.y * height(u, v));
+      }
+    }
+    Self::new(heights, nx, nz, corner, size.x, size.z, material)
+  }
+
+  // Grayscale brightness maps to height, white being size.y
+  pub fn from_image(
+    path: &str,
+    corner: Vec3,
+    size: Vec3,
+    material: Material,
+  ) -> image::ImageResult<Self> {
+    let img = image::open(path)?.to_luma();
+    let (nx, nz) = (img.width() as usize, img.height() as usize);
+    let heights = img
+      .pixels()
+      .map(|pixel| size.y * pixel[0] as f64 / 255.0)
+      .collect();
+    Ok(Self::new(heights, nx, nz, corner, size.x, size.z, material))
+  }
+
+  fn height(&self, i: usize, j: usize) -> f64 {
+    self.heights[j * self.nx + i]
+  }
+
+  fn vertex(&self, i: usize, j: usize) -> Vec3 {
+    vec3(
+      self.corner.x + i as f64 * self.cell_x,
+      self.height(i, j),
+      self.corner.z + j as f64 * self.cell_z,
+    )
+  }
+
+  // Central differences, one sided along the edges
+  fn vertex_normals(&self) -> Vec<Vec3> {
+    let mut normals = Vec::with_capacity(self.nx * self.nz);
+    for j in 0..self.nz {
+      for i in 0..self.nx {
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+        let slope_x = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * self.cell_x);
+        let slope_z = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * self.cell_z);
+        normals.push(vec3(-slope_x, 1.0, -slope_z).unit());
+      }
+    }
+    normals
+  }
+
+  fn hit_cell(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+    let mut best: Option<(f64, Vec3, bool)> = None;
+    let mut closest = t_max;
+
+    for &(a, b, c) in &[(0, 1, 2), (0, 2, 3)] {
+      let (va, vb, vc) = (corners[a], corners[b], corners[c]);
+      let (pa, pb, pc) = (
+        self.vertex(va.0, va.1),
+        self.vertex(vb.0, vb.1),
+        self.vertex(vc.0, vc.1),
+      );
+      if let Some((t, b1, b2)) = hit_triangle(r, pa, pb, pc) {
+        if t > t_min && t < closest {
+          closest = t;
+          let normal = scalar(1.0 - b1 - b2) * self.normals[va.1 * self.nx + va.0]
+            + scalar(b1) * self.normals[vb.1 * self.nx + vb.0]
+            + scalar(b2) * self.normals[vc.1 * self.nx + vc.0];
+          // The smoothed normal can lean past the ray at grazing angles, the
+          // triangle's own one can't
+          let up = (pc - pa).cross(pb - pa);
+          best = Some((t, normal.unit(), r.direction().dot(up) < 0.0));
+        }
+      }
+    }
+
+    let (t, normal, front_face) = best?;
+    let p = r.point_at_parameter(t);
+    Some(HitRecord {
+      t,
+      u: (p.x - self.boxy.min.x) / (self.boxy.max.x - self.boxy.min.x),
+      v: (p.z - self.boxy.min.z) / (self.boxy.max.z - self.boxy.min.z),
+      p,
+      normal,
+      front_face,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  // Same two triangles as hit_cell, but any crossing will do
+  fn cell_occluded(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> bool {
+    let corners = [
+      self.vertex(i, j),
+      self.vertex(i + 1, j),
+      self.vertex(i + 1, j + 1),
+      self.vertex(i, j + 1),
+    ];
+    [(0, 1, 2), (0, 2, 3)].iter().any(|&(a, b, c)| {
+      matches!(
+        hit_triangle(r, corners[a], corners[b], corners[c]),
+        Some((t, _, _)) if t > t_min && t < t_max
+      )
+    })
+  }
+
+  // Calls `visit` with each column of cells the ray passes through where it
+  // could touch the surface, nearest first, until one returns something
+  fn walk<T>(
+    &self,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    mut visit: impl FnMut(usize, usize) -> Option<T>,
+  ) -> Option<T> {
+    let (t_enter, t_exit) = self.box_span(r, t_min, t_max)?;
+    let o = r.origin();
+    let d = r.direction();
+    let start = r.point_at_parameter(t_enter);
+
+    let cells_x = self.nx - 1;
+    let cells_z = self.nz - 1;
+    let cell_index = |offset: f64, size: f64, cells: usize| {
+      ((offset / size).floor().max(0.0) as usize).min(cells - 1)
+    };
+    let mut i = cell_index(start.x - self.corner.x, self.cell_x, cells_x);
+    let mut j = cell_index(start.z - self.corner.z, self.cell_z, cells_z);
+
+    // 2D DDA, t_next is where the ray leaves the current column along each axis
+    let (step_i, mut t_next_x, t_delta_x) = dda_axis(o.x, d.x, self.corner.x, self.cell_x, i);
+    let (step_j, mut t_next_z, t_delta_z) = dda_axis(o.z, d.z, self.corner.z, self.cell_z, j);
+
+    let mut t_cell_enter = t_enter;
+    loop {
+      let t_cell_exit = t_next_x.min(t_next_z).min(t_exit);
+
+      // Skip cells the ray passes entirely above or below
+      let y0 = o.y + t_cell_enter * d.y;
+      let y1 = o.y + t_cell_exit * d.y;
+      let heights = [
+        self.height(i, j),
+        self.height(i + 1, j),
+        self.height(i, j + 1),
+        self.height(i + 1, j + 1),
+      ];
+      let low = heights.iter().cloned().fold(f64::INFINITY, f64::min);
+      let high = heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+      if y0.min(y1) <= high && y0.max(y1) >= low {
+        if let Some(found) = visit(i, j) {
+          return Some(found);
+        }
+      }
+
+      if t_cell_exit >= t_exit {
+        return None;
+      }
+      t_cell_enter = t_cell_exit;
+      if t_next_x < t_next_z {
+        if (step_i < 0 && i == 0) || (step_i > 0 && i + 1 >= cells_x) {
+          return None;
+        }
+        i = (i as isize + step_i) as usize;
+        t_next_x += t_delta_x;
+      } else {
+        if (step_j < 0 && j == 0) || (step_j > 0 && j + 1 >= cells_z) {
+          return None;
+        }
+        j = (j as isize + step_j) as usize;
+        t_next_z += t_delta_z;
+      }
+    }
+  }
+
+  // Where the ray is inside the bounding box, clipped to [t_min, t_max]
+  fn box_span(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+    for a in 0..3 {
+      let inv_d = r.inv_direction()[a];
+      let mut t0 = (self.boxy.min[a] - r.origin()[a]) * inv_d;
+      let mut t1 = (self.boxy.max[a] - r.origin()[a]) * inv_d;
+      if inv_d < 0.0 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+      t_min = t_min.max(t0);
+      t_max = t_max.min(t1);
+      if t_max < t_min {
+        return None;
+      }
+    }
+    Some((t_min, t_max))
+  }
+}
+
+// Moller-Trumbore, gives t and the barycentric weights of b and c
+fn hit_triangle(r: &Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<(f64, f64, f64)> {
+  let edge1 = b - a;
+  let edge2 = c - a;
+  let pvec = r.direction().cross(edge2);
+  let det = edge1.dot(pvec);
+  if det.abs() < 1e-12 {
+    return None;
+  }
+  let inv_det = 1.0 / det;
+  let tvec = r.origin() - a;
+  let b1 = tvec.dot(pvec) * inv_det;
+  if !(0.0..=1.0).contains(&b1) {
+    return None;
+  }
+  let qvec = tvec.cross(edge1);
+  let b2 = r.direction().dot(qvec) * inv_det;
+  if b2 < 0.0 || b1 + b2 > 1.0 {
+    return None;
+  }
+  Some((edge2.dot(qvec) * inv_det, b1, b2))
+}
+
+impl Hitable for Heightfield {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    self.walk(r, t_min, t_max, |i, j| self.hit_cell(r, i, j, t_min, t_max))
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self
+      .walk(r, t_min, t_max, |i, j| {
+        self.cell_occluded(r, i, j, t_min, t_max).then_some(())
+      })
+      .is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(self.boxy)
+  }
+}
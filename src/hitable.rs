@@ -1,7 +1,11 @@
 use rand::prelude::*;
+use std::sync::Arc;
 
 use crate::bvh::*;
+use crate::mat4::Mat4;
 use crate::material::*;
+use crate::onb::ONB;
+use crate::random_on_unit_sphere;
 use crate::ray::Ray;
 use crate::texture::Texture;
 use crate::vec3::*;
@@ -13,6 +17,11 @@ pub struct HitRecord<'a> {
   pub v: f64,
   pub p: Vec3,
   pub normal: Vec3,
+  // Whether the ray came from the side the surface faces. Normals usually
+  // tell you that too, but not interpolated ones or after FlipNormals.
+  pub front_face: bool,
+  // Direction along a curve, zero for surfaces that don't have one
+  pub tangent: Vec3,
   pub material: Option<&'a Material>,
 }
 
@@ -20,6 +29,13 @@ pub trait Hitable: std::fmt::Debug + Send + Sync {
   fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
   fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb>;
 
+  // Whether anything is hit between t_min and t_max, for shadow rays and
+  // light visibility. Nothing needs to be closest and no HitRecord gets
+  // filled in, so overriding this can skip most of the work of hit().
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.hit(r, t_min, t_max).is_some()
+  }
+
   fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
     0.0
   }
@@ -27,8 +43,36 @@ pub trait Hitable: std::fmt::Debug + Send + Sync {
   fn random(&self, o: Vec3) -> Vec3 {
     vec3(1.0, 0.0, 0.0)
   }
+
+  // Every stretch of the whole line where the ray is inside a closed surface,
+  // as (entry, exit) pairs sorted by t. CSG needs these instead of just the
+  // nearest hit. By default it walks the line with repeated hits, which works
+  // for anything closed but primitives that can solve for both sides at once
+  // should.
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    let mut boundaries = vec![];
+    let mut t = -f64::INFINITY;
+    while boundaries.len() < MAX_INTERVAL_HITS {
+      if let Some(rec) = self.hit(r, t, f64::INFINITY) {
+        t = rec.t + INTERVAL_EPSILON * rec.t.abs().max(1.0);
+        boundaries.push(rec);
+      } else {
+        break;
+      }
+    }
+
+    let mut boundaries = boundaries.into_iter();
+    let mut intervals = vec![];
+    while let (Some(entry), Some(exit)) = (boundaries.next(), boundaries.next()) {
+      intervals.push((entry, exit));
+    }
+    intervals
+  }
 }
 
+const MAX_INTERVAL_HITS: usize = 64;
+const INTERVAL_EPSILON: f64 = 1e-7;
+
 #[derive(Debug)]
 pub struct Sphere {
   pub center: Vec3,
@@ -60,10 +104,11 @@ impl Hitable for Sphere {
       if temp < t_max && temp > t_min {
         rec.t = temp;
         rec.p = r.point_at_parameter(rec.t);
-        let (u, v) = get_sphere_uv(rec.p);
+        let (u, v) = get_sphere_uv((rec.p - self.center) / scalar(self.radius));
         rec.u = u;
         rec.v = v;
         rec.normal = (rec.p - self.center) / scalar(self.radius);
+        rec.front_face = r.direction().dot(rec.normal) < 0.0;
         rec.material = Some(&self.material);
         return Some(rec);
       }
@@ -72,10 +117,11 @@ impl Hitable for Sphere {
       if temp < t_max && temp > t_min {
         rec.t = temp;
         rec.p = r.point_at_parameter(rec.t);
-        let (u, v) = get_sphere_uv(rec.p);
+        let (u, v) = get_sphere_uv((rec.p - self.center) / scalar(self.radius));
         rec.u = u;
         rec.v = v;
         rec.normal = (rec.p - self.center) / scalar(self.radius);
+        rec.front_face = r.direction().dot(rec.normal) < 0.0;
         rec.material = Some(&self.material);
         return Some(rec);
       }
@@ -83,22 +129,223 @@ impl Hitable for Sphere {
     None
   }
 
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    sphere_occludes(self.center, self.radius, r, t_min, t_max)
+  }
+
   fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
     return Some(Aabb::new(
       self.center - scalar(self.radius),
       self.center + scalar(self.radius),
     ));
   }
+
+  // Uniform over the cone of directions the sphere covers from o
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    if !self.occluded(&Ray::new(o, v), 0.001, f64::MAX) {
+      return 0.0;
+    }
+    let distance_squared = (self.center - o).squared_length();
+    let radius_squared = self.radius * self.radius;
+    if distance_squared <= radius_squared {
+      // From inside every direction hits it
+      return 1.0 / (4.0 * std::f64::consts::PI);
+    }
+    let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+    1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_theta_max))
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let direction = self.center - o;
+    let distance_squared = direction.squared_length();
+    if distance_squared <= self.radius * self.radius {
+      return random_on_unit_sphere();
+    }
+    ONB::build_from_w(direction).local(random_to_sphere(self.radius, distance_squared))
+  }
+
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    let oc = r.origin() - self.center;
+    let a = r.direction().dot(r.direction());
+    let b = oc.dot(r.direction());
+    let c = oc.dot(oc) - self.radius * self.radius;
+    let discriminant = b * b - a * c;
+    if discriminant <= 0.0 {
+      return vec![];
+    }
+
+    let record = |t: f64| {
+      let p = r.point_at_parameter(t);
+      let (u, v) = get_sphere_uv((p - self.center) / scalar(self.radius));
+      HitRecord {
+        t,
+        u,
+        v,
+        p,
+        normal: (p - self.center) / scalar(self.radius),
+        front_face: r.direction().dot(p - self.center) < 0.0,
+        tangent: scalar(0.0),
+        material: Some(&self.material),
+      }
+    };
+    vec![(
+      record((-b - discriminant.sqrt()) / a),
+      record((-b + discriminant.sqrt()) / a),
+    )]
+  }
+}
+
+// Whether either root of the sphere's quadratic is between t_min and t_max
+fn sphere_occludes(center: Vec3, radius: f64, r: &Ray, t_min: f64, t_max: f64) -> bool {
+  let oc = r.origin() - center;
+  let a = r.direction().dot(r.direction());
+  let b = oc.dot(r.direction());
+  let c = oc.dot(oc) - radius * radius;
+  let discriminant = b * b - a * c;
+  if discriminant <= 0.0 {
+    return false;
+  }
+  let root = discriminant.sqrt();
+  [(-b - root) / a, (-b + root) / a]
+    .iter()
+    .any(|&t| t > t_min && t < t_max)
+}
+
+// Direction inside the cone around +z that a sphere `distance_squared` away covers
+fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+  let mut rng = rand::thread_rng();
+  let r1 = rng.gen::<f64>();
+  let r2 = rng.gen::<f64>();
+  let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+  let phi = 2.0 * std::f64::consts::PI * r1;
+  let sin_theta = (1.0 - z * z).sqrt();
+  vec3(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
+// Moves linearly from center0 at time0 to center1 at time1
+#[derive(Debug)]
+pub struct MovingSphere {
+  pub center0: Vec3,
+  pub center1: Vec3,
+  pub time0: f64,
+  pub time1: f64,
+  pub radius: f64,
+  pub material: Material,
+}
+
+impl MovingSphere {
+  pub fn new(
+    center0: Vec3,
+    center1: Vec3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Material,
+  ) -> Self {
+    Self {
+      center0,
+      center1,
+      time0,
+      time1,
+      radius,
+      material,
+    }
+  }
+
+  pub fn center(&self, time: f64) -> Vec3 {
+    // Both times the same means it never moves, and the lerp would be 0 / 0
+    if self.time1 == self.time0 {
+      return self.center0;
+    }
+    self.center0
+      + scalar((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
+  }
+}
+
+impl Hitable for MovingSphere {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let center = self.center(r.time());
+    let oc = r.origin() - center;
+    let a = r.direction().dot(r.direction());
+    let b = oc.dot(r.direction());
+    let c = oc.dot(oc) - self.radius * self.radius;
+    let discriminant = b * b - a * c;
+    if discriminant > 0.0 {
+      for &temp in &[
+        (-b - discriminant.sqrt()) / a,
+        (-b + discriminant.sqrt()) / a,
+      ] {
+        if temp < t_max && temp > t_min {
+          let p = r.point_at_parameter(temp);
+          let (u, v) = get_sphere_uv((p - center) / scalar(self.radius));
+          return Some(HitRecord {
+            t: temp,
+            u,
+            v,
+            p,
+            normal: (p - center) / scalar(self.radius),
+            front_face: r.direction().dot(p - center) < 0.0,
+            tangent: scalar(0.0),
+            material: Some(&self.material),
+          });
+        }
+      }
+    }
+    None
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    sphere_occludes(self.center(r.time()), self.radius, r, t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    let box0 = Aabb::new(
+      self.center(t0) - scalar(self.radius),
+      self.center(t0) + scalar(self.radius),
+    );
+    let box1 = Aabb::new(
+      self.center(t1) - scalar(self.radius),
+      self.center(t1) + scalar(self.radius),
+    );
+    Some(surrounding_box(box0, box1))
+  }
 }
 
 #[derive(Debug)]
 pub struct HitableList {
   pub list: Vec<Box<dyn Hitable>>,
+  // How often random() picks each member, summing to 1, or empty for all the
+  // same. Giving lights their power sends more samples toward the bright ones.
+  pub weights: Vec<f64>,
 }
 
 impl HitableList {
   pub fn new(list: Vec<Box<dyn Hitable>>) -> Self {
-    Self { list }
+    Self {
+      list,
+      weights: vec![],
+    }
+  }
+
+  pub fn with_weights(mut self, weights: Vec<f64>) -> Self {
+    assert_eq!(
+      weights.len(),
+      self.list.len(),
+      "HitableList needs one weight per member"
+    );
+    // Normalized once here, random() and pdf_value() go through every weight
+    let total = weights.iter().sum::<f64>();
+    assert!(total > 0.0, "HitableList weights need a positive total");
+    self.weights = weights.into_iter().map(|weight| weight / total).collect();
+    self
+  }
+
+  fn weight(&self, i: usize) -> f64 {
+    if self.weights.is_empty() {
+      1.0 / self.list.len() as f64
+    } else {
+      self.weights[i]
+    }
   }
 }
 
@@ -116,6 +363,13 @@ impl Hitable for HitableList {
     return hit_anything;
   }
 
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self
+      .list
+      .iter()
+      .any(|hitable| hitable.occluded(r, t_min, t_max))
+  }
+
   fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
     if self.list.len() < 1 {
       return None;
@@ -126,11 +380,64 @@ impl Hitable for HitableList {
     }
     Some(boxy)
   }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    self
+      .list
+      .iter()
+      .enumerate()
+      .map(|(i, hitable)| self.weight(i) * hitable.pdf_value(o, v))
+      .sum()
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    if self.list.is_empty() {
+      return vec3(1.0, 0.0, 0.0);
+    }
+    let mut pick = rand::thread_rng().gen::<f64>();
+    for (i, hitable) in self.list.iter().enumerate() {
+      pick -= self.weight(i);
+      if pick < 0.0 {
+        return hitable.random(o);
+      }
+    }
+    self.list[self.list.len() - 1].random(o)
+  }
+}
+
+// Solid angle pdf towards a shape whose `random` picks points uniformly by area
+pub fn area_pdf_value(hitable: &dyn Hitable, area: f64, o: Vec3, v: Vec3) -> f64 {
+  if let Some(rec) = hitable.hit(&Ray::new(o, v), 0.001, std::f64::MAX) {
+    hit_pdf_value(&rec, area, v)
+  } else {
+    0.0
+  }
+}
+
+// Same, for shapes a direction can cross more than once whose `random` picks
+// from all of the surface, hidden side included. Every crossing along v could
+// have been the point picked, so each one adds to the pdf.
+pub fn surface_pdf_value(hitable: &dyn Hitable, area: f64, o: Vec3, v: Vec3) -> f64 {
+  let r = Ray::new(o, v);
+  let mut pdf = 0.0;
+  let mut t_min = 0.001;
+  while let Some(rec) = hitable.hit(&r, t_min, f64::MAX) {
+    pdf += hit_pdf_value(&rec, area, v);
+    t_min = rec.t;
+  }
+  pdf
 }
 
-fn get_sphere_uv(p: Vec3) -> (f64, f64) {
+fn hit_pdf_value(rec: &HitRecord, area: f64, v: Vec3) -> f64 {
+  let distance_squared = rec.t * rec.t * v.squared_length();
+  let cosine = (v.dot(rec.normal) / v.length()).abs();
+  distance_squared / (cosine * area)
+}
+
+// p is on the unit sphere around the origin, so the texture moves with the sphere
+pub fn get_sphere_uv(p: Vec3) -> (f64, f64) {
   let phi = p.z.atan2(p.x);
-  let theta = p.y.asin();
+  let theta = p.y.clamp(-1.0, 1.0).asin();
   let pi = std::f64::consts::PI;
   let u = 1.0 - (phi + pi) / (2.0 * pi);
   let v = (theta + pi / 2.0) / pi;
@@ -158,10 +465,9 @@ impl XYRect {
       material,
     }
   }
-}
 
-impl Hitable for XYRect {
-  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+  // Where the ray meets the rect, as (t, x, y)
+  fn crossing(&self, r: &Ray, t0: f64, t1: f64) -> Option<(f64, f64, f64)> {
     let t = (self.k - r.origin().z) / r.direction().z;
     if t < t0 || t > t1 {
       return None;
@@ -171,7 +477,13 @@ impl Hitable for XYRect {
     if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
       return None;
     }
+    Some((t, x, y))
+  }
+}
 
+impl Hitable for XYRect {
+  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+    let (t, x, y) = self.crossing(r, t0, t1)?;
     Some(HitRecord {
       u: (x - self.x0) / (self.x1 - self.x0),
       v: (y - self.y0) / (self.y1 - self.y0),
@@ -179,15 +491,36 @@ impl Hitable for XYRect {
       material: Some(&self.material),
       p: r.point_at_parameter(t),
       normal: vec3(0.0, 0.0, 1.0),
+      front_face: r.direction().z < 0.0,
+      tangent: scalar(0.0),
     })
   }
 
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossing(r, t_min, t_max).is_some()
+  }
+
   fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
     Some(Aabb::new(
       vec3(self.x0, self.y0, self.k - 0.0001),
       vec3(self.x1, self.y1, self.k + 0.0001),
     ))
   }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+    area_pdf_value(self, area, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let random_point = vec3(
+      self.x0 + rng.gen::<f64>() * (self.x1 - self.x0),
+      self.y0 + rng.gen::<f64>() * (self.y1 - self.y0),
+      self.k,
+    );
+    random_point - o
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -211,10 +544,9 @@ impl XZRect {
       material,
     }
   }
-}
 
-impl Hitable for XZRect {
-  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+  // Where the ray meets the rect, as (t, x, z)
+  fn crossing(&self, r: &Ray, t0: f64, t1: f64) -> Option<(f64, f64, f64)> {
     let t = (self.k - r.origin().y) / r.direction().y;
     if t < t0 || t > t1 {
       return None;
@@ -224,7 +556,13 @@ impl Hitable for XZRect {
     if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
       return None;
     }
+    Some((t, x, z))
+  }
+}
 
+impl Hitable for XZRect {
+  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+    let (t, x, z) = self.crossing(r, t0, t1)?;
     Some(HitRecord {
       u: (x - self.x0) / (self.x1 - self.x0),
       v: (z - self.z0) / (self.z1 - self.z0),
@@ -232,9 +570,15 @@ impl Hitable for XZRect {
       material: Some(&self.material),
       p: r.point_at_parameter(t),
       normal: vec3(0.0, 1.0, 0.0),
+      front_face: r.direction().y < 0.0,
+      tangent: scalar(0.0),
     })
   }
 
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossing(r, t_min, t_max).is_some()
+  }
+
   fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
     Some(Aabb::new(
       vec3(self.x0, self.k - 0.0001, self.z0),
@@ -243,14 +587,8 @@ impl Hitable for XZRect {
   }
 
   fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
-    if let Some(rec) = self.hit(&Ray::new(o, v), 0.001, std::f64::MAX) {
-      let area = (self.x1 - self.x0) * (self.z1 - self.z0);
-      let distance_squared = rec.t * rec.t * v.squared_length();
-      let cosine = (v.dot(rec.normal) / v.length()).abs();
-      distance_squared / (cosine * area)
-    } else {
-      0.0
-    }
+    let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+    area_pdf_value(self, area, o, v)
   }
 
   fn random(&self, o: Vec3) -> Vec3 {
@@ -285,10 +623,9 @@ impl YZRect {
       material,
     }
   }
-}
 
-impl Hitable for YZRect {
-  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+  // Where the ray meets the rect, as (t, y, z)
+  fn crossing(&self, r: &Ray, t0: f64, t1: f64) -> Option<(f64, f64, f64)> {
     let t = (self.k - r.origin().x) / r.direction().x;
     if t < t0 || t > t1 {
       return None;
@@ -298,7 +635,13 @@ impl Hitable for YZRect {
     if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
       return None;
     }
+    Some((t, y, z))
+  }
+}
 
+impl Hitable for YZRect {
+  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+    let (t, y, z) = self.crossing(r, t0, t1)?;
     Some(HitRecord {
       u: (y - self.y0) / (self.y1 - self.y0),
       v: (z - self.z0) / (self.z1 - self.z0),
@@ -306,120 +649,405 @@ impl Hitable for YZRect {
       material: Some(&self.material),
       p: r.point_at_parameter(t),
       normal: vec3(1.0, 0.0, 0.0),
+      front_face: r.direction().x < 0.0,
+      tangent: scalar(0.0),
     })
   }
 
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossing(r, t_min, t_max).is_some()
+  }
+
   fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
     Some(Aabb::new(
       vec3(self.k - 0.0001, self.y0, self.z0),
       vec3(self.k + 0.0001, self.y1, self.z1),
     ))
   }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+    area_pdf_value(self, area, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let random_point = vec3(
+      self.k,
+      self.y0 + rng.gen::<f64>() * (self.y1 - self.y0),
+      self.z0 + rng.gen::<f64>() * (self.z1 - self.z0),
+    );
+    random_point - o
+  }
 }
 
+// Parallelogram with a corner at `q` and edges `u` and `v`. The normal is
+// u x v, so the winding picks which side is the front.
 #[derive(Debug, Clone)]
-pub enum FlipNormals {
-  OkayXY(XYRect),
-  OkayXZ(XZRect),
-  OkayYZ(YZRect),
+pub struct Quad {
+  pub q: Vec3,
+  pub u: Vec3,
+  pub v: Vec3,
+  pub material: Material,
+  // Both sides act like the front, normal always faces the ray
+  pub two_sided: bool,
+  normal: Vec3,
+  d: f64,
+  w: Vec3,
+  area: f64,
 }
 
-use FlipNormals::*;
+impl Quad {
+  pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Material) -> Self {
+    let n = u.cross(v);
+    let normal = n.unit();
+    Self {
+      q,
+      u,
+      v,
+      material,
+      two_sided: false,
+      normal,
+      d: normal.dot(q),
+      w: n / scalar(n.dot(n)),
+      area: n.length(),
+    }
+  }
+
+  pub fn two_sided(mut self) -> Self {
+    self.two_sided = true;
+    self
+  }
 
-impl FlipNormals {
-  pub fn new_xy(hitable: XYRect) -> Self {
-    OkayXY(hitable)
+  // Where the ray meets the quad, as (t, alpha, beta, normal . direction)
+  fn crossing(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64, f64)> {
+    let denom = self.normal.dot(r.direction());
+    if denom.abs() < 1e-8 {
+      return None;
+    }
+    let t = (self.d - self.normal.dot(r.origin())) / denom;
+    if t < t_min || t > t_max {
+      return None;
+    }
+
+    let planar = r.point_at_parameter(t) - self.q;
+    let alpha = self.w.dot(planar.cross(self.v));
+    let beta = self.w.dot(self.u.cross(planar));
+    if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+      return None;
+    }
+    Some((t, alpha, beta, denom))
+  }
+}
+
+impl Hitable for Quad {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let (t, alpha, beta, denom) = self.crossing(r, t_min, t_max)?;
+    Some(HitRecord {
+      t,
+      u: alpha,
+      v: beta,
+      p: r.point_at_parameter(t),
+      normal: if self.two_sided && denom > 0.0 {
+        -self.normal
+      } else {
+        self.normal
+      },
+      front_face: self.two_sided || denom < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
   }
-  pub fn new_xz(hitable: XZRect) -> Self {
-    OkayXZ(hitable)
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossing(r, t_min, t_max).is_some()
   }
-  pub fn new_yz(hitable: YZRect) -> Self {
-    OkayYZ(hitable)
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+    let mut min = self.q;
+    let mut max = self.q;
+    for corner in corners.iter() {
+      for c in 0..3 {
+        min[c] = min[c].min(corner[c]);
+        max[c] = max[c].max(corner[c]);
+      }
+    }
+    // Same padding as the rects, so axis aligned quads don't get a flat box
+    Some(Aabb::new(min - scalar(0.0001), max + scalar(0.0001)))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    area_pdf_value(self, self.area, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    self.q + scalar(rng.gen::<f64>()) * self.u + scalar(rng.gen::<f64>()) * self.v - o
   }
 }
 
-impl Hitable for FlipNormals {
-  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
-    let rec = match &self {
-      OkayXY(hitable) => hitable.hit(r, t0, t1),
-      OkayXZ(hitable) => hitable.hit(r, t0, t1),
-      OkayYZ(hitable) => hitable.hit(r, t0, t1),
-    };
-    if let Some(mut rec) = rec {
-      rec.normal = -rec.normal;
-      return Some(rec);
+// Infinite plane through `point`. It has no bounding box, so BvhNode keeps
+// it out of the tree and tests it against every ray.
+#[derive(Debug, Clone)]
+pub struct Plane {
+  pub point: Vec3,
+  pub normal: Vec3,
+  pub material: Material,
+  // Texture coordinates repeat every unit along these
+  u_axis: Vec3,
+  v_axis: Vec3,
+}
+
+impl Plane {
+  pub fn new(point: Vec3, normal: Vec3, material: Material) -> Self {
+    let uvw = ONB::build_from_w(normal);
+    Self {
+      point,
+      normal: uvw.w,
+      material,
+      u_axis: uvw.u,
+      v_axis: uvw.v,
     }
-    None
   }
-  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
-    match &self {
-      OkayXY(hitable) => hitable.bounding_box(t0, t1),
-      OkayXZ(hitable) => hitable.bounding_box(t0, t1),
-      OkayYZ(hitable) => hitable.bounding_box(t0, t1),
+
+  // Where the ray meets the plane, as (t, normal . direction)
+  fn crossing(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+    let denom = self.normal.dot(r.direction());
+    if denom.abs() < 1e-8 {
+      return None;
+    }
+    let t = (self.point - r.origin()).dot(self.normal) / denom;
+    if t < t_min || t > t_max {
+      return None;
     }
+    Some((t, denom))
+  }
+}
+
+impl Hitable for Plane {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let (t, denom) = self.crossing(r, t_min, t_max)?;
+    let p = r.point_at_parameter(t);
+    let planar = p - self.point;
+    Some(HitRecord {
+      t,
+      u: planar.dot(self.u_axis).rem_euclid(1.0),
+      v: planar.dot(self.v_axis).rem_euclid(1.0),
+      p,
+      normal: self.normal,
+      front_face: denom < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossing(r, t_min, t_max).is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    None
   }
 }
 
+// Turns any hitable inside out, the back becomes the front
 #[derive(Debug)]
+pub struct FlipNormals {
+  hitable: Box<dyn Hitable>,
+}
+
+impl FlipNormals {
+  pub fn new(hitable: Box<dyn Hitable>) -> Self {
+    Self { hitable }
+  }
+}
+
+impl Hitable for FlipNormals {
+  fn hit(&self, r: &Ray, t0: f64, t1: f64) -> Option<HitRecord> {
+    let mut rec = self.hitable.hit(r, t0, t1)?;
+    rec.normal = -rec.normal;
+    rec.front_face = !rec.front_face;
+    Some(rec)
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.hitable.occluded(r, t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    self.hitable.bounding_box(t0, t1)
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    self.hitable.pdf_value(o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    self.hitable.random(o)
+  }
+}
+
+// Axis aligned box, intersected as the overlap of three slabs
+#[derive(Debug, Clone)]
 pub struct Cuboid {
   pmin: Vec3,
   pmax: Vec3,
-  list: HitableList,
+  pub material: Material,
+}
+
+impl Cuboid {
+  pub fn new(p0: Vec3, p1: Vec3, material: Material) -> Self {
+    Self {
+      pmin: vec3(p0.x.min(p1.x), p0.y.min(p1.y), p0.z.min(p1.z)),
+      pmax: vec3(p0.x.max(p1.x), p0.y.max(p1.y), p0.z.max(p1.z)),
+      material,
+    }
+  }
+
+  // Where the ray's line enters and leaves the box, with the axis of the
+  // face it crosses each time
+  fn slabs(&self, r: &Ray) -> Option<((f64, usize), (f64, usize))> {
+    let mut near = (-f64::INFINITY, 0);
+    let mut far = (f64::INFINITY, 0);
+    for a in 0..3 {
+      let o = r.origin()[a];
+      let d = r.direction()[a];
+      if d == 0.0 {
+        // Parallel, either always between these two planes or never
+        if o < self.pmin[a] || o > self.pmax[a] {
+          return None;
+        }
+        continue;
+      }
+      let mut t0 = (self.pmin[a] - o) / d;
+      let mut t1 = (self.pmax[a] - o) / d;
+      if d < 0.0 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+      if t0 > near.0 {
+        near = (t0, a);
+      }
+      if t1 < far.0 {
+        far = (t1, a);
+      }
+    }
+    if near.0 > far.0 {
+      return None;
+    }
+    Some((near, far))
+  }
+
+  fn record(&self, r: &Ray, t: f64, axis: usize, entering: bool) -> HitRecord {
+    let p = r.point_at_parameter(t);
+    let d = r.direction()[axis];
+    let mut normal = scalar(0.0);
+    normal[axis] = if entering { -d.signum() } else { d.signum() };
+
+    // Same u and v as the rect for that face
+    let (a, b) = match axis {
+      0 => (1, 2),
+      1 => (0, 2),
+      _ => (0, 1),
+    };
+    let size = self.pmax - self.pmin;
+    HitRecord {
+      t,
+      u: (p[a] - self.pmin[a]) / size[a],
+      v: (p[b] - self.pmin[b]) / size[b],
+      p,
+      normal,
+      front_face: entering,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    }
+  }
+
+  // Faces that can be seen from o as (axis, position, area), all of them
+  // from inside
+  fn visible_faces(&self, o: Vec3) -> Vec<(usize, f64, f64)> {
+    let size = self.pmax - self.pmin;
+    let mut faces = vec![];
+    let mut all = vec![];
+    for a in 0..3 {
+      let area = size[(a + 1) % 3] * size[(a + 2) % 3];
+      if o[a] < self.pmin[a] {
+        faces.push((a, self.pmin[a], area));
+      } else if o[a] > self.pmax[a] {
+        faces.push((a, self.pmax[a], area));
+      }
+      all.push((a, self.pmin[a], area));
+      all.push((a, self.pmax[a], area));
+    }
+    if faces.is_empty() {
+      all
+    } else {
+      faces
+    }
+  }
 }
 
 impl Hitable for Cuboid {
   fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-    self.list.hit(r, t_min, t_max)
+    let ((t_near, near_axis), (t_far, far_axis)) = self.slabs(r)?;
+    if t_near > t_min && t_near < t_max {
+      Some(self.record(r, t_near, near_axis, true))
+    } else if t_far > t_min && t_far < t_max {
+      Some(self.record(r, t_far, far_axis, false))
+    } else {
+      None
+    }
   }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    match self.slabs(r) {
+      Some(((t_near, _), (t_far, _))) => {
+        (t_near > t_min && t_near < t_max) || (t_far > t_min && t_far < t_max)
+      }
+      None => false,
+    }
+  }
+
   fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
     Some(Aabb::new(self.pmin, self.pmax))
   }
-}
 
-impl Cuboid {
-  pub fn new(p0: Vec3, p1: Vec3, material: Material) -> Self {
-    let list: Vec<Box<dyn Hitable>> = vec![
-      Box::new(XYRect::new(p0.x, p1.x, p0.y, p1.y, p1.z, material.clone())),
-      Box::new(FlipNormals::new_xy(XYRect::new(
-        p0.x,
-        p1.x,
-        p0.y,
-        p1.y,
-        p0.z,
-        material.clone(),
-      ))),
-      Box::new(XZRect::new(
-        p0.x,
-        p1.x,
-        p0.z,
-        p1.z,
-        p1.y + 0.01,
-        material.clone(),
-      )),
-      Box::new(FlipNormals::new_xz(XZRect::new(
-        p0.x,
-        p1.x,
-        p0.z,
-        p1.z,
-        p0.y,
-        material.clone(),
-      ))),
-      Box::new(YZRect::new(p0.y, p1.y, p0.z, p1.z, p1.x, material.clone())),
-      Box::new(FlipNormals::new_yz(YZRect::new(
-        p0.y,
-        p1.y,
-        p0.z,
-        p1.z,
-        p0.x,
-        material.clone(),
-      ))),
-    ];
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    match self.slabs(r) {
+      Some(((t_near, near_axis), (t_far, far_axis))) => vec![(
+        self.record(r, t_near, near_axis, true),
+        self.record(r, t_far, far_axis, false),
+      )],
+      None => vec![],
+    }
+  }
 
-    Self {
-      pmin: p0,
-      pmax: p1,
-      list: HitableList::new(list),
+  // Only the faces turned toward o get sampled, and from outside a convex
+  // box each direction crosses exactly one of them first
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    let area = self.visible_faces(o).iter().map(|face| face.2).sum();
+    area_pdf_value(self, area, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let faces = self.visible_faces(o);
+    let total: f64 = faces.iter().map(|face| face.2).sum();
+
+    let mut pick = rng.gen::<f64>() * total;
+    let mut chosen = faces[faces.len() - 1];
+    for face in faces {
+      pick -= face.2;
+      if pick < 0.0 {
+        chosen = face;
+        break;
+      }
     }
+
+    let (axis, position, _) = chosen;
+    let mut point = self.pmin + vec3(rng.gen(), rng.gen(), rng.gen()) * (self.pmax - self.pmin);
+    point[axis] = position;
+    point - o
   }
 }
 
@@ -437,19 +1065,32 @@ impl Translate {
 
 impl Hitable for Translate {
   fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-    let moved_r = Ray::new(r.origin() - self.offset, r.direction());
+    let moved_r = Ray::new_at_time(r.origin() - self.offset, r.direction(), r.time());
     if let Some(mut rec) = self.hitable.hit(&moved_r, t_min, t_max) {
       rec.p += self.offset;
       return Some(rec);
     }
     None
   }
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    let moved_r = Ray::new_at_time(r.origin() - self.offset, r.direction(), r.time());
+    self.hitable.occluded(&moved_r, t_min, t_max)
+  }
+
   fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
     if let Some(boxy) = self.hitable.bounding_box(t0, t1) {
       return Some(Aabb::new(boxy.min + self.offset, boxy.max + self.offset));
     }
     None
   }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    self.hitable.pdf_value(o - self.offset, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    self.hitable.random(o - self.offset)
+  }
 }
 
 #[derive(Debug)]
@@ -457,54 +1098,39 @@ pub struct RotateY {
   hitable: Box<dyn Hitable>,
   sin_theta: f64,
   cos_theta: f64,
-  hasbox: bool,
-  boxy: Aabb,
 }
 
 impl RotateY {
   pub fn new(hitable: Box<dyn Hitable>, angle: f64) -> Self {
     let pi = std::f64::consts::PI;
     let radians = (pi / 180.0) * angle;
-    let sin_theta = radians.sin();
-    let cos_theta = radians.cos();
-    let boxy = hitable.bounding_box(0.0, 1.0);
-    let hasbox = boxy.is_some();
-    let mut min = scalar(std::f64::MAX);
-    let mut max = scalar(std::f64::MIN);
-
-    //WHAT?
-    let bbox = boxy.expect("DOUBLE CHECK WHAT HAPPENS HERE"); //unwrap_or(Aabb::new(scalar(0.0),scalar(0.0)));
-    for i in 0..2 {
-      for j in 0..2 {
-        for k in 0..2 {
-          let x = i as f64 * bbox.max.x + (1.0 - i as f64) * bbox.min.x;
-          let y = j as f64 * bbox.max.y + (1.0 - j as f64) * bbox.min.y;
-          let z = k as f64 * bbox.max.z + (1.0 - k as f64) * bbox.min.z;
-
-          let newx = cos_theta * x + sin_theta * z;
-          let newz = -sin_theta * x + cos_theta * z;
-          let tester = vec3(newx, y, newz);
-          for c in 0..3 {
-            if tester[c] > max[c] {
-              max[c] = tester[c];
-            }
-            if tester[c] < min[c] {
-              min[c] = tester[c];
-            }
-          }
-        }
-      }
-    }
     Self {
       hitable,
-      sin_theta,
-      cos_theta,
-      hasbox,
-      boxy: Aabb::new(min, max),
+      sin_theta: radians.sin(),
+      cos_theta: radians.cos(),
     }
   }
 }
 
+impl RotateY {
+  // Same rotation hit() applies to the ray, and its inverse for the results
+  fn to_object(&self, a: Vec3) -> Vec3 {
+    vec3(
+      self.cos_theta * a.x - self.sin_theta * a.z,
+      a.y,
+      self.sin_theta * a.x + self.cos_theta * a.z,
+    )
+  }
+
+  fn to_world(&self, a: Vec3) -> Vec3 {
+    vec3(
+      self.cos_theta * a.x + self.sin_theta * a.z,
+      a.y,
+      -self.sin_theta * a.x + self.cos_theta * a.z,
+    )
+  }
+}
+
 impl Hitable for RotateY {
   fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
     let mut origin = r.origin();
@@ -515,7 +1141,7 @@ impl Hitable for RotateY {
     origin.z = sin_theta * r.origin().x + cos_theta * r.origin().z;
     direction.x = cos_theta * r.direction().x - sin_theta * r.direction().z;
     direction.z = sin_theta * r.direction().x + cos_theta * r.direction().z;
-    let rotated_r = Ray::new(origin, direction);
+    let rotated_r = Ray::new_at_time(origin, direction, r.time());
 
     if let Some(mut rec) = self.hitable.hit(&rotated_r, t_min, t_max) {
       let mut p = rec.p;
@@ -524,18 +1150,294 @@ impl Hitable for RotateY {
       p.z = -sin_theta * rec.p.x + cos_theta * rec.p.z;
       normal.x = cos_theta * rec.normal.x + sin_theta * rec.normal.z;
       normal.z = -sin_theta * rec.normal.x + cos_theta * rec.normal.z;
+      let tangent = rec.tangent;
+      rec.tangent.x = cos_theta * tangent.x + sin_theta * tangent.z;
+      rec.tangent.z = -sin_theta * tangent.x + cos_theta * tangent.z;
       rec.p = p;
       rec.normal = normal;
       return Some(rec);
     }
     None
   }
-  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
-    if self.hasbox {
-      Some(self.boxy)
-    } else {
-      None
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    let rotated_r = Ray::new_at_time(
+      self.to_object(r.origin()),
+      self.to_object(r.direction()),
+      r.time(),
+    );
+    self.hitable.occluded(&rotated_r, t_min, t_max)
+  }
+
+  // Worked out per call, the box of something moving depends on the shutter.
+  // Unbounded stays unbounded.
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    let bbox = self.hitable.bounding_box(t0, t1)?;
+    let mut min = scalar(std::f64::MAX);
+    let mut max = scalar(std::f64::MIN);
+    for i in 0..2 {
+      for j in 0..2 {
+        for k in 0..2 {
+          let x = i as f64 * bbox.max.x + (1.0 - i as f64) * bbox.min.x;
+          let y = j as f64 * bbox.max.y + (1.0 - j as f64) * bbox.min.y;
+          let z = k as f64 * bbox.max.z + (1.0 - k as f64) * bbox.min.z;
+
+          let tester = self.to_world(vec3(x, y, z));
+          for c in 0..3 {
+            if tester[c] > max[c] {
+              max[c] = tester[c];
+            }
+            if tester[c] < min[c] {
+              min[c] = tester[c];
+            }
+          }
+        }
+      }
+    }
+    Some(Aabb::new(min, max))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    self.hitable.pdf_value(self.to_object(o), self.to_object(v))
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    self.to_world(self.hitable.random(self.to_object(o)))
+  }
+}
+
+#[derive(Debug)]
+pub struct Transform {
+  hitable: Box<dyn Hitable>,
+  matrix: Mat4,
+  inverse: Mat4,
+}
+
+impl Transform {
+  pub fn new(hitable: Box<dyn Hitable>, matrix: Mat4) -> Self {
+    let inverse = matrix
+      .inverse()
+      .expect("Transform matrix is not invertible");
+    Self {
+      hitable,
+      matrix,
+      inverse,
+    }
+  }
+
+  pub fn translate(hitable: Box<dyn Hitable>, offset: Vec3) -> Self {
+    Self::new(hitable, Mat4::translation(offset))
+  }
+
+  pub fn rotate_x(hitable: Box<dyn Hitable>, angle: f64) -> Self {
+    Self::new(hitable, Mat4::rotation_x(angle))
+  }
+
+  pub fn rotate_y(hitable: Box<dyn Hitable>, angle: f64) -> Self {
+    Self::new(hitable, Mat4::rotation_y(angle))
+  }
+
+  pub fn rotate_z(hitable: Box<dyn Hitable>, angle: f64) -> Self {
+    Self::new(hitable, Mat4::rotation_z(angle))
+  }
+
+  pub fn rotate(hitable: Box<dyn Hitable>, axis: Vec3, angle: f64) -> Self {
+    Self::new(hitable, Mat4::rotation(axis, angle))
+  }
+
+  pub fn scale(hitable: Box<dyn Hitable>, factors: Vec3) -> Self {
+    Self::new(hitable, Mat4::scale(factors))
+  }
+
+  pub fn look_at(hitable: Box<dyn Hitable>, from: Vec3, to: Vec3, up: Vec3) -> Self {
+    Self::new(hitable, Mat4::look_at(from, to, up))
+  }
+
+  // Applies another transform on top of this one, instead of nesting wrappers
+  pub fn then(self, matrix: Mat4) -> Self {
+    Self::new(self.hitable, matrix * self.matrix)
+  }
+}
+
+impl Hitable for Transform {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    transformed_hit(&*self.hitable, &self.matrix, &self.inverse, r, t_min, t_max)
+  }
+
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    transformed_intervals(&*self.hitable, &self.matrix, &self.inverse, r)
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self
+      .hitable
+      .occluded(&local_ray(&self.inverse, r), t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    self
+      .hitable
+      .bounding_box(t0, t1)
+      .map(|boxy| self.matrix.transform_aabb(boxy))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    transformed_pdf_value(&*self.hitable, &self.matrix, &self.inverse, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    transformed_random(&*self.hitable, &self.matrix, &self.inverse, o)
+  }
+}
+
+pub fn transformed_hit<'a>(
+  hitable: &'a dyn Hitable,
+  matrix: &Mat4,
+  inverse: &Mat4,
+  r: &Ray,
+  t_min: f64,
+  t_max: f64,
+) -> Option<HitRecord<'a>> {
+  let local_r = local_ray(inverse, r);
+  let rec = hitable.hit(&local_r, t_min, t_max)?;
+  Some(world_record(rec, matrix, inverse))
+}
+
+pub fn transformed_intervals<'a>(
+  hitable: &'a dyn Hitable,
+  matrix: &Mat4,
+  inverse: &Mat4,
+  r: &Ray,
+) -> Vec<(HitRecord<'a>, HitRecord<'a>)> {
+  let local_r = local_ray(inverse, r);
+  hitable
+    .intervals(&local_r)
+    .into_iter()
+    .map(|(entry, exit)| {
+      (
+        world_record(entry, matrix, inverse),
+        world_record(exit, matrix, inverse),
+      )
+    })
+    .collect()
+}
+
+// The matrix squashes and stretches directions unevenly, so a unit of solid
+// angle in object space covers |det| / |inverse * v|^3 of it in world space
+// (v unit length), and the pdf shrinks by as much
+pub fn transformed_pdf_value(
+  hitable: &dyn Hitable,
+  matrix: &Mat4,
+  inverse: &Mat4,
+  o: Vec3,
+  v: Vec3,
+) -> f64 {
+  let local_v = inverse.transform_vector(v.unit());
+  let pdf = hitable.pdf_value(inverse.transform_point(o), local_v);
+  if pdf == 0.0 {
+    return 0.0;
+  }
+  let x = matrix.transform_vector(vec3(1.0, 0.0, 0.0));
+  let y = matrix.transform_vector(vec3(0.0, 1.0, 0.0));
+  let z = matrix.transform_vector(vec3(0.0, 0.0, 1.0));
+  let det = x.dot(y.cross(z)).abs();
+  pdf / (det * local_v.length().powi(3))
+}
+
+pub fn transformed_random(hitable: &dyn Hitable, matrix: &Mat4, inverse: &Mat4, o: Vec3) -> Vec3 {
+  matrix.transform_vector(hitable.random(inverse.transform_point(o)))
+}
+
+pub fn local_ray(inverse: &Mat4, r: &Ray) -> Ray {
+  // The direction isn't renormalized, so t is the same in both spaces
+  Ray::new_at_time(
+    inverse.transform_point(r.origin()),
+    inverse.transform_vector(r.direction()),
+    r.time(),
+  )
+}
+
+fn world_record<'a>(mut rec: HitRecord<'a>, matrix: &Mat4, inverse: &Mat4) -> HitRecord<'a> {
+  rec.p = matrix.transform_point(rec.p);
+  rec.normal = inverse.transform_normal(rec.normal).unit();
+  rec.tangent = matrix.transform_vector(rec.tangent);
+  if rec.tangent.squared_length() > 0.0 {
+    rec.tangent = rec.tangent.unit();
+  }
+  rec
+}
+
+// Shares its geometry (usually a prebuilt BvhNode) with every other instance
+// of the same object, so only the transform and material are per placement
+#[derive(Debug, Clone)]
+pub struct Instance {
+  object: Arc<dyn Hitable>,
+  matrix: Mat4,
+  inverse: Mat4,
+  material: Option<Material>,
+}
+
+impl Instance {
+  pub fn new(object: Arc<dyn Hitable>, matrix: Mat4) -> Self {
+    let inverse = matrix.inverse().expect("Instance matrix is not invertible");
+    Self {
+      object,
+      matrix,
+      inverse,
+      material: None,
+    }
+  }
+
+  pub fn with_material(mut self, material: Material) -> Self {
+    self.material = Some(material);
+    self
+  }
+
+  pub fn set_matrix(&mut self, matrix: Mat4) {
+    self.inverse = matrix.inverse().expect("Instance matrix is not invertible");
+    self.matrix = matrix;
+  }
+}
+
+impl Hitable for Instance {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut rec = transformed_hit(&*self.object, &self.matrix, &self.inverse, r, t_min, t_max)?;
+    if let Some(material) = &self.material {
+      rec.material = Some(material);
+    }
+    Some(rec)
+  }
+
+  fn intervals(&self, r: &Ray) -> Vec<(HitRecord, HitRecord)> {
+    let mut intervals = transformed_intervals(&*self.object, &self.matrix, &self.inverse, r);
+    if let Some(material) = &self.material {
+      for (entry, exit) in intervals.iter_mut() {
+        entry.material = Some(material);
+        exit.material = Some(material);
+      }
     }
+    intervals
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self
+      .object
+      .occluded(&local_ray(&self.inverse, r), t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    self
+      .object
+      .bounding_box(t0, t1)
+      .map(|boxy| self.matrix.transform_aabb(boxy))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    transformed_pdf_value(&*self.object, &self.matrix, &self.inverse, o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    transformed_random(&*self.object, &self.matrix, &self.inverse, o)
   }
 }
 
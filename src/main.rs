@@ -6,30 +6,54 @@ use std::env;
 use std::fs;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+mod animation;
 mod bvh;
 mod camera;
+mod csg;
+mod curve;
+mod dda;
+mod grid;
+mod heightfield;
 mod hitable;
+mod mat4;
 mod material;
 mod onb;
 mod pdf;
 mod perlin;
+mod quadric;
+mod quat;
 mod ray;
 mod scenes;
+mod sdf;
+mod stats;
 mod texture;
+mod tlas;
+mod torus;
 pub mod vec3;
+mod wide_bvh;
 
 use bvh::*;
 use camera::Camera;
+use grid::Grid;
 use hitable::*;
 use material::*;
 use pdf::*;
 use ray::Ray;
 use scenes::*;
+use stats::Counters;
 use texture::*;
 use vec3::*;
+use wide_bvh::WideBvh;
 
 const SKYBOX_COLOR: f64 = 0.0;
+const FRAMES_PER_SECOND: f64 = 24.0;
+// Portion of each frame the shutter is open for, 0.5 is a 180 degree shutter
+const SHUTTER_FRACTION: f64 = 0.5;
+// Camera rays across and down in bench mode
+const BENCH_SIZE: usize = 256;
+const RENDER_THREADS: isize = 8;
 
 fn main() -> std::io::Result<()> {
   /*
@@ -44,7 +68,16 @@ fn main() -> std::io::Result<()> {
   return Ok(());
   */
 
-  let args: Vec<String> = env::args().collect();
+  let mut args: Vec<String> = env::args().collect();
+  if args.len() > 1 && args[1] == "bench" {
+    bench();
+    return Ok(());
+  }
+
+  // `--grid` anywhere renders with a Grid instead of the BVH, to compare them
+  let use_grid = args.iter().any(|arg| arg == "--grid");
+  args.retain(|arg| arg != "--grid");
+
   let factor: isize = if args.len() > 1 {
     args[1].parse().unwrap()
   } else {
@@ -57,17 +90,91 @@ fn main() -> std::io::Result<()> {
     100
   };
 
+  // More than one frame renders a sequence, with animation evaluated at each
+  // frame's time instead of over the scene camera's shutter
+  let frames: isize = if args.len() > 3 {
+    args[3].parse().unwrap()
+  } else {
+    1
+  };
+
   let nx: isize = 100 * factor;
   let ny: isize = 100 * factor;
   let ns: isize = quality;
 
   //let (camera, world) = light_sphere_scene(nx as f64 / ny as f64);
   let (camera, world) = cornell_box_scene(nx as f64 / ny as f64);
+  // What color() aims its light samples at, has to match the scene
+  let lights: Arc<dyn Hitable> = Arc::new(cornell_box_lights());
+
+  // Leaves are shared, so building a tree for each frame only clones pointers
+  let world = world
+    .list
+    .into_iter()
+    .map(Arc::from)
+    .collect::<Vec<Arc<dyn Hitable>>>();
+
+  let count = fs::read_dir("output/")?.count();
+
+  if frames == 1 {
+    let accel: Arc<dyn Hitable> = if use_grid {
+      Arc::new(Grid::new(&world, camera.time0, camera.time1))
+    } else {
+      let bvh = FlatBvh::from_tree(BvhNode::new_parallel(&world, camera.time0, camera.time1));
+      println!("BVH: {}", bvh.stats());
+      Arc::new(bvh)
+    };
+    let (buffer, counters) = render(Arc::new(camera), accel, lights, nx, ny, ns);
+    let file_name = format!("output/hello_world_{}.png", count);
+    image::save_buffer(&file_name, &buffer, nx as u32, ny as u32, image::RGB(8)).unwrap();
+    save_heatmaps(&file_name, &counters, nx, ny, ns);
+    return Ok(());
+  }
 
-  let world = Arc::new(world);
-  let camera = Arc::new(camera);
+  // Bounds depend on the shutter, but nothing gets added or removed between
+  // frames, so one tree is refit to each frame instead of built again
+  let mut bvh_world = if use_grid {
+    None
+  } else {
+    let first = camera.with_shutter(0.0, SHUTTER_FRACTION / FRAMES_PER_SECOND);
+    let bvh = FlatBvh::from_tree(BvhNode::new_parallel(&world, first.time0, first.time1));
+    println!("BVH: {}", bvh.stats());
+    Some(Arc::new(bvh))
+  };
+
+  for frame in 0..frames {
+    let time = frame as f64 / FRAMES_PER_SECOND;
+    let camera = camera.with_shutter(time, SHUTTER_FRACTION / FRAMES_PER_SECOND);
+    let accel: Arc<dyn Hitable> = match bvh_world.as_mut() {
+      Some(bvh) => {
+        if frame > 0 {
+          Arc::get_mut(bvh)
+            .expect("BVH still shared after render")
+            .refit(camera.time0, camera.time1);
+        }
+        Arc::clone(bvh) as Arc<dyn Hitable>
+      }
+      // Grids have no refit, a new one goes over each frame
+      None => Arc::new(Grid::new(&world, camera.time0, camera.time1)),
+    };
+    let (buffer, counters) = render(Arc::new(camera), accel, Arc::clone(&lights), nx, ny, ns);
+    let file_name = format!("output/hello_world_{}_frame_{:04}.png", count, frame);
+    image::save_buffer(&file_name, &buffer, nx as u32, ny as u32, image::RGB(8)).unwrap();
+    save_heatmaps(&file_name, &counters, nx, ny, ns);
+  }
 
-  let threads: isize = 8;
+  Ok(())
+}
+
+fn render(
+  camera: Arc<Camera>,
+  world: Arc<dyn Hitable>,
+  lights: Arc<dyn Hitable>,
+  nx: isize,
+  ny: isize,
+  ns: isize,
+) -> (Vec<u8>, Vec<Counters>) {
+  let threads = RENDER_THREADS;
   let outer_result: Arc<Mutex<Vec<Option<_>>>> = Arc::new(Mutex::new(vec![None; threads as usize]));
 
   let mut handles = vec![];
@@ -80,18 +187,15 @@ fn main() -> std::io::Result<()> {
     let camera = Arc::clone(&camera);
     let outer_result = Arc::clone(&outer_result);
     let world = Arc::clone(&world);
+    let lights = Arc::clone(&lights);
 
     let handle = thread::spawn(move || {
-      let mut temp = world
-        .list
-        .iter()
-        .map(|s| s.as_ref())
-        .collect::<Vec<&dyn Hitable>>();
-      let bvh_world = BvhNode::new(&mut temp, 0.0, 0.0);
-
       let mut rng = rand::thread_rng();
 
       let mut result: Vec<Vec3> = vec![];
+      // What the acceleration structure did for each pixel
+      let mut counters = vec![];
+      stats::take();
 
       for j in (0..ny).rev() {
         for i in 0..nx {
@@ -105,17 +209,18 @@ fn main() -> std::io::Result<()> {
             let r = camera.get_ray(u, v);
             let _p = r.point_at_parameter(2.0);
             let depth = 0;
-            col += de_nan(color(&r, &bvh_world, depth));
+            col += de_nan(color(&r, &*world, &*lights, depth));
             //col += color(&r, &*world, depth);
           }
 
           col /= scalar(ns as f64);
 
           result.push(col);
+          counters.push(stats::take());
         }
       }
 
-      outer_result.lock().unwrap()[x as usize] = Some(result);
+      outer_result.lock().unwrap()[x as usize] = Some((result, counters));
     });
 
     handles.push(handle);
@@ -126,10 +231,15 @@ fn main() -> std::io::Result<()> {
   }
 
   let mut result = vec![scalar(0.0); (nx * ny) as usize];
+  let mut counters = vec![Counters::default(); (nx * ny) as usize];
   for r in outer_result.lock().unwrap().iter_mut() {
-    for (col, other) in result.iter_mut().zip(r.as_ref().unwrap().iter()) {
+    let (colors, thread_counters) = r.as_ref().unwrap();
+    for (col, other) in result.iter_mut().zip(colors.iter()) {
       *col += *other
     }
+    for (count, other) in counters.iter_mut().zip(thread_counters.iter()) {
+      *count += *other
+    }
   }
   let mut buffer: Vec<u8> = vec![];
 
@@ -154,15 +264,166 @@ fn main() -> std::io::Result<()> {
     buffer.push(ib);
   }
 
-  let count = fs::read_dir("output/")?.count();
-  let file_name = format!("output/hello_world_{}.png", count);
+  (buffer, counters)
+}
 
-  image::save_buffer(file_name, &buffer, nx as u32, ny as u32, image::RGB(8)).unwrap();
+// Node visits and primitive tests per sample as heatmaps next to the image,
+// red being the pixel that needed the most. Nothing gets counted unless it's
+// built with `--features stats`.
+fn save_heatmaps(file_name: &str, counters: &[Counters], nx: isize, ny: isize, ns: isize) {
+  if !cfg!(feature = "stats") {
+    return;
+  }
+  let samples = (ns / RENDER_THREADS * RENDER_THREADS) as f64;
+  let maps = [
+    (
+      "nodes",
+      "node visits",
+      counters.iter().map(|c| c.node_visits).collect::<Vec<_>>(),
+    ),
+    (
+      "tests",
+      "primitive tests",
+      counters
+        .iter()
+        .map(|c| c.primitive_tests)
+        .collect::<Vec<_>>(),
+    ),
+  ];
+  let total = counters.iter().fold(Counters::default(), |mut total, c| {
+    total += *c;
+    total
+  });
+  println!(
+    "{:.1} box tests per sample",
+    total.box_tests as f64 / samples / counters.len() as f64
+  );
+
+  for (suffix, name, counts) in maps.iter() {
+    let per_sample = counts
+      .iter()
+      .map(|&count| count as f64 / samples)
+      .collect::<Vec<_>>();
+    let mean = per_sample.iter().sum::<f64>() / per_sample.len() as f64;
+    let max = per_sample.iter().cloned().fold(0.0, f64::max);
+    println!("{:.1} {} per sample, {:.1} at most", mean, name, max);
+
+    let heatmap_name = file_name.replace(".png", &format!("_{}.png", suffix));
+    image::save_buffer(
+      heatmap_name,
+      &stats::heatmap(&per_sample),
+      nx as u32,
+      ny as u32,
+      image::RGB(8),
+    )
+    .unwrap();
+  }
+}
 
-  Ok(())
+// `raytracing bench` times building and tracing each kind of BVH instead of
+// rendering anything
+fn bench() {
+  let mut rng = rand::thread_rng();
+  let (camera, mut cloud) = cornell_box_scene(1.0);
+  let white = Lambertian::new_from_color(scalar(0.73));
+  for _ in 0..100_000 {
+    let center = scalar(555.0) * vec3(rng.gen(), rng.gen(), rng.gen());
+    let radius = 1.0 + 2.0 * rng.gen::<f64>();
+    cloud
+      .list
+      .push(Box::new(Sphere::new(center, radius, white.clone())));
+  }
+  let scenes = vec![
+    ("cornell box", cornell_box_scene(1.0)),
+    ("cornell hair", cornell_hair_scene(1.0)),
+    ("100k spheres", (camera, cloud)),
+  ];
+
+  for (name, (camera, list)) in scenes {
+    let world = list
+      .list
+      .into_iter()
+      .map(Arc::from)
+      .collect::<Vec<Arc<dyn Hitable>>>();
+    let (time0, time1) = (camera.time0, camera.time1);
+    let rays = bench_rays(&camera, &BvhNode::new(&world, time0, time1));
+    println!("{}: {} hitables, {} rays", name, world.len(), rays.len());
+
+    let start = Instant::now();
+    let tree = BvhNode::new(&world, time0, time1);
+    bench_trace("BvhNode", &tree, start.elapsed(), &rays);
+
+    let start = Instant::now();
+    let flat = FlatBvh::from_tree(BvhNode::new(&world, time0, time1));
+    bench_trace("FlatBvh", &flat, start.elapsed(), &rays);
+
+    let start = Instant::now();
+    let wide = WideBvh::from_tree(BvhNode::new(&world, time0, time1));
+    bench_trace("WideBvh", &wide, start.elapsed(), &rays);
+
+    let start = Instant::now();
+    let grid = Grid::new(&world, time0, time1);
+    bench_trace("Grid", &grid, start.elapsed(), &rays);
+  }
 }
 
-fn color(r: &Ray, world: &dyn Hitable, depth: isize) -> Vec3 {
+// Camera rays, plus a bounce in a random direction off whatever they hit
+fn bench_rays(camera: &Camera, world: &dyn Hitable) -> Vec<Ray> {
+  let mut rng = rand::thread_rng();
+  let mut rays = vec![];
+  for j in 0..BENCH_SIZE {
+    for i in 0..BENCH_SIZE {
+      let u = (i as f64 + rng.gen::<f64>()) / BENCH_SIZE as f64;
+      let v = (j as f64 + rng.gen::<f64>()) / BENCH_SIZE as f64;
+      let r = camera.get_ray(u, v);
+      if let Some(rec) = world.hit(&r, 0.001, f64::INFINITY) {
+        rays.push(Ray::new_at_time(rec.p, random_on_unit_sphere(), r.time()));
+      }
+      rays.push(r);
+    }
+  }
+  rays
+}
+
+fn bench_trace(name: &str, world: &dyn Hitable, build: Duration, rays: &[Ray]) {
+  let start = Instant::now();
+  let hits = rays
+    .iter()
+    .filter(|r| world.hit(r, 0.001, f64::INFINITY).is_some())
+    .count();
+  let rate = rays.len() as f64 / start.elapsed().as_secs_f64() / 1e6;
+
+  // The same rays as shadow rays, where any hit will do
+  let start = Instant::now();
+  let occluded = rays
+    .iter()
+    .filter(|r| world.occluded(r, 0.001, f64::INFINITY))
+    .count();
+  let occluded_rate = rays.len() as f64 / start.elapsed().as_secs_f64() / 1e6;
+
+  println!(
+    "  {}: built in {:.1?}, {:.2} Mrays/s, {:.2} Mrays/s any hit, {} hits, {} occluded",
+    name, build, rate, occluded_rate, hits, occluded
+  );
+}
+
+// Light arriving along a scattered ray. When it's aimed at a light and nothing
+// in the world is in the way, the light is all color() would find, so a
+// shadow ray stands in for tracing it through the whole scene.
+fn incoming(r: &Ray, world: &dyn Hitable, lights: &dyn Hitable, depth: isize) -> Vec3 {
+  if let Some(rec) = lights.hit(r, 0.001, f64::INFINITY) {
+    // Stop just short, the light is in the world too
+    if !world.occluded(r, 0.001, rec.t * (1.0 - 1e-4)) {
+      return rec
+        .material
+        .expect("Missing material on a light")
+        .emitted(r, &rec, rec.u, rec.v, rec.p);
+    }
+  }
+  color(r, world, lights, depth)
+}
+
+fn color(r: &Ray, world: &dyn Hitable, lights: &dyn Hitable, depth: isize) -> Vec3 {
   if let Some(mut rec) = world.hit(r, 0.001, std::f64::INFINITY) {
     // ON A DIFFUSE LIGHT, rec.u and rec.v don't matter, texture is constant
     // internally so it just returns color
@@ -174,16 +435,17 @@ fn color(r: &Ray, world: &dyn Hitable, depth: isize) -> Vec3 {
       let material = rec.material.take();
       if let Some((albedo, scattered, pdf)) = material.as_ref().and_then(|m| m.scatter(r, &mut rec))
       {
-        let light = DiffuseLight::new(Texture::new_constant(scalar(15.0)));
-        let light_shape = XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light);
+        if material.is_some_and(|m| m.is_specular()) {
+          return emitted + albedo * color(&scattered, world, lights, depth + 1) / scalar(pdf);
+        }
 
-        let p0 = HitablePDF::new(&light_shape, rec.p);
+        let p0 = HitablePDF::new(lights, rec.p);
 
         let p1 = CosinePDF::new(rec.normal);
 
         let p = MixturePDF::new(p0, p1);
 
-        let scattered = Ray::new(rec.p, p.generate());
+        let scattered = Ray::new_at_time(rec.p, p.generate(), r.time());
         let pdf_val = p.value(scattered.direction());
 
         return emitted
@@ -193,7 +455,7 @@ fn color(r: &Ray, world: &dyn Hitable, depth: isize) -> Vec3 {
                 .expect("Missing material in here")
                 .scattering_pdf(r, &mut rec, &scattered),
             )
-            * color(&scattered, world, depth + 1)
+            * incoming(&scattered, world, lights, depth + 1)
             / scalar(pdf_val);
       }
     }
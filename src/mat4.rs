@@ -0,0 +1,240 @@
+use std::ops::Mul;
+
+use crate::bvh::Aabb;
+use crate::vec3::*;
+
+// Row-major, and points are column vectors, so `a * b` applies b first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+  pub m: [[f64; 4]; 4],
+}
+
+impl Default for Mat4 {
+  fn default() -> Self {
+    Self::identity()
+  }
+}
+
+impl Mat4 {
+  pub fn new(m: [[f64; 4]; 4]) -> Self {
+    Self { m }
+  }
+
+  pub fn identity() -> Self {
+    Self::new([
+      [1.0, 0.0, 0.0, 0.0],
+      [0.0, 1.0, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  pub fn translation(offset: Vec3) -> Self {
+    Self::new([
+      [1.0, 0.0, 0.0, offset.x],
+      [0.0, 1.0, 0.0, offset.y],
+      [0.0, 0.0, 1.0, offset.z],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  pub fn scale(factors: Vec3) -> Self {
+    Self::new([
+      [factors.x, 0.0, 0.0, 0.0],
+      [0.0, factors.y, 0.0, 0.0],
+      [0.0, 0.0, factors.z, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  // Angles are in degrees, same as RotateY
+  pub fn rotation_x(angle: f64) -> Self {
+    let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+    Self::new([
+      [1.0, 0.0, 0.0, 0.0],
+      [0.0, cos_theta, -sin_theta, 0.0],
+      [0.0, sin_theta, cos_theta, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  pub fn rotation_y(angle: f64) -> Self {
+    let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+    Self::new([
+      [cos_theta, 0.0, sin_theta, 0.0],
+      [0.0, 1.0, 0.0, 0.0],
+      [-sin_theta, 0.0, cos_theta, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  pub fn rotation_z(angle: f64) -> Self {
+    let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+    Self::new([
+      [cos_theta, -sin_theta, 0.0, 0.0],
+      [sin_theta, cos_theta, 0.0, 0.0],
+      [0.0, 0.0, 1.0, 0.0],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  // Rodrigues' rotation about an arbitrary axis through the origin
+  pub fn rotation(axis: Vec3, angle: f64) -> Self {
+    let a = axis.unit();
+    let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+    let k = 1.0 - cos_theta;
+    Self::new([
+      [
+        a.x * a.x * k + cos_theta,
+        a.x * a.y * k - a.z * sin_theta,
+        a.x * a.z * k + a.y * sin_theta,
+        0.0,
+      ],
+      [
+        a.x * a.y * k + a.z * sin_theta,
+        a.y * a.y * k + cos_theta,
+        a.y * a.z * k - a.x * sin_theta,
+        0.0,
+      ],
+      [
+        a.x * a.z * k - a.y * sin_theta,
+        a.y * a.z * k + a.x * sin_theta,
+        a.z * a.z * k + cos_theta,
+        0.0,
+      ],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  // Places an object at `from` with its local +z axis pointing at `to`
+  pub fn look_at(from: Vec3, to: Vec3, up: Vec3) -> Self {
+    let w = (to - from).unit();
+    let u = up.cross(w).unit();
+    let v = w.cross(u);
+    Self::new([
+      [u.x, v.x, w.x, from.x],
+      [u.y, v.y, w.y, from.y],
+      [u.z, v.z, w.z, from.z],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+
+  pub fn transpose(&self) -> Self {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+      for (j, value) in row.iter_mut().enumerate() {
+        *value = self.m[j][i];
+      }
+    }
+    Self::new(m)
+  }
+
+  // Gauss-Jordan with partial pivoting, None if the matrix is singular
+  pub fn inverse(&self) -> Option<Self> {
+    let mut a = self.m;
+    let mut inv = Self::identity().m;
+
+    for col in 0..4 {
+      let mut pivot = col;
+      for row in (col + 1)..4 {
+        if a[row][col].abs() > a[pivot][col].abs() {
+          pivot = row;
+        }
+      }
+      if a[pivot][col].abs() < 1e-12 {
+        return None;
+      }
+      a.swap(col, pivot);
+      inv.swap(col, pivot);
+
+      let scale = 1.0 / a[col][col];
+      for j in 0..4 {
+        a[col][j] *= scale;
+        inv[col][j] *= scale;
+      }
+
+      for row in 0..4 {
+        if row == col {
+          continue;
+        }
+        let factor = a[row][col];
+        if factor == 0.0 {
+          continue;
+        }
+        for j in 0..4 {
+          a[row][j] -= factor * a[col][j];
+          inv[row][j] -= factor * inv[col][j];
+        }
+      }
+    }
+
+    Some(Self::new(inv))
+  }
+
+  pub fn transform_point(&self, p: Vec3) -> Vec3 {
+    let m = &self.m;
+    let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+    let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+    let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+    let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+    if w == 1.0 {
+      vec3(x, y, z)
+    } else {
+      vec3(x / w, y / w, z / w)
+    }
+  }
+
+  pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+    let m = &self.m;
+    vec3(
+      m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+      m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+      m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+  }
+
+  // Normals transform by the inverse transpose, so call this on the inverse
+  // of the matrix that moved the surface. The result is not normalized.
+  pub fn transform_normal(&self, n: Vec3) -> Vec3 {
+    let m = &self.m;
+    vec3(
+      m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+      m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+      m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+    )
+  }
+
+  // Arvo's method, gives the same box as transforming all eight corners
+  pub fn transform_aabb(&self, boxy: Aabb) -> Aabb {
+    let mut min = vec3(self.m[0][3], self.m[1][3], self.m[2][3]);
+    let mut max = min;
+    for i in 0..3 {
+      for j in 0..3 {
+        let a = self.m[i][j] * boxy.min[j];
+        let b = self.m[i][j] * boxy.max[j];
+        if a < b {
+          min[i] += a;
+          max[i] += b;
+        } else {
+          min[i] += b;
+          max[i] += a;
+        }
+      }
+    }
+    Aabb::new(min, max)
+  }
+}
+
+impl Mul for Mat4 {
+  type Output = Self;
+
+  fn mul(self, other: Self) -> Self {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+      for (j, value) in row.iter_mut().enumerate() {
+        *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+      }
+    }
+    Self::new(m)
+  }
+}
@@ -14,6 +14,7 @@ pub enum Material {
   //OkayDielectric(Dielectric),
   OkayDiffuseLight(DiffuseLight),
   //OkayIsotropic(Isotropic),
+  OkayHair(Hair),
 }
 
 use Material::*;
@@ -22,6 +23,7 @@ impl Material {
     match self {
       OkayLambertian(inner) => inner.scatter(r_in, rec),
       OkayDiffuseLight(inner) => inner.scatter(r_in, rec),
+      OkayHair(inner) => inner.scatter(r_in, rec),
       _ => None,
       /*
       OkayMetal(inner) => inner.scatter(r_in, rec),
@@ -40,6 +42,12 @@ impl Material {
     }
   }
 
+  // Picks its own scattered direction and returns the full weight for it,
+  // instead of leaving color() to sample the lights and a cosine lobe
+  pub fn is_specular(&self) -> bool {
+    matches!(self, OkayHair(_))
+  }
+
   pub fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
     match self {
       OkayDiffuseLight(inner) => inner.emitted(r_in, rec, u, v, p),
@@ -64,10 +72,10 @@ impl Lambertian {
     })
   }
 
-  pub fn scatter(&self, _r_in: &Ray, rec: &mut HitRecord) -> Option<(Vec3, Ray, f64)> {
+  pub fn scatter(&self, r_in: &Ray, rec: &mut HitRecord) -> Option<(Vec3, Ray, f64)> {
     let uvw = ONB::build_from_w(rec.normal);
     let direction = uvw.local(random_cosine_direction());
-    let scattered = Ray::new(rec.p, direction.unit());
+    let scattered = Ray::new_at_time(rec.p, direction.unit(), r_in.time());
     let albedo = self.albedo.value(rec.u, rec.v, rec.p);
     let pdf = uvw.w.dot(scattered.direction()) / std::f64::consts::PI;
     Some((albedo, scattered, pdf))
@@ -194,8 +202,8 @@ impl DiffuseLight {
     None
   }
 
-  pub fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
-    if rec.normal.dot(r_in.direction()) < 0.0 {
+  pub fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Vec3) -> Vec3 {
+    if rec.front_face {
       self.emit.value(u, v, p)
     } else {
       scalar(0.0)
@@ -221,3 +229,344 @@ impl Isotropic {
   }
 }
 */
+
+const PI: f64 = std::f64::consts::PI;
+const HAIR_ETA: f64 = 1.55;
+// R, TT and TRT get their own lobes, everything after them is lumped together
+const HAIR_P_MAX: usize = 3;
+
+// Hair fiber after d'Eon et al. and pbrt's HairBSDF. Light reflects off the
+// surface (R), goes straight through (TT) or reflects once inside on the way
+// (TRT). It reads the fiber direction from rec.tangent and the offset across
+// the fiber from rec.v, which is what Curve fills in.
+#[derive(Debug, Clone)]
+pub struct Hair {
+  // Absorption inside the fiber, per diameter travelled
+  pub sigma_a: Vec3,
+  // Longitudinal and azimuthal roughness, 0 to 1
+  pub beta_m: f64,
+  pub beta_n: f64,
+  // Tilt of the cuticle scales in degrees, about 2 for human hair
+  pub alpha: f64,
+  v: [f64; HAIR_P_MAX + 1],
+  s: f64,
+  sin_2k_alpha: [f64; 3],
+  cos_2k_alpha: [f64; 3],
+}
+
+impl Hair {
+  pub fn new(sigma_a: Vec3, beta_m: f64, beta_n: f64, alpha: f64) -> Material {
+    let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+    let s = (PI / 8.0).sqrt() * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+
+    // The scales tilt each lobe by a different multiple of alpha
+    let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+    let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+    for i in 1..3 {
+      sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+      cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+    }
+
+    OkayHair(Self {
+      sigma_a,
+      beta_m,
+      beta_n,
+      alpha,
+      v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+      s,
+      sin_2k_alpha,
+      cos_2k_alpha,
+    })
+  }
+
+  // Concentrations of the brown-black and the red pigment. Around 8 of the
+  // first is black hair, 1.3 brown and 0.3 blonde.
+  pub fn new_from_melanin(
+    eumelanin: f64,
+    pheomelanin: f64,
+    beta_m: f64,
+    beta_n: f64,
+    alpha: f64,
+  ) -> Material {
+    let sigma_a =
+      scalar(eumelanin) * vec3(0.419, 0.697, 1.37) + scalar(pheomelanin) * vec3(0.187, 0.4, 1.05);
+    Self::new(sigma_a, beta_m, beta_n, alpha)
+  }
+
+  // Absorption that makes a thick mass of this hair come out about `color`
+  pub fn new_from_color(color: Vec3, beta_m: f64, beta_n: f64, alpha: f64) -> Material {
+    let denom = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+      + 5.574 * beta_n.powi(4)
+      + 0.245 * beta_n.powi(5);
+    let channel = |c: f64| (c.ln() / denom).powi(2);
+    Self::new(
+      vec3(channel(color.x), channel(color.y), channel(color.z)),
+      beta_m,
+      beta_n,
+      alpha,
+    )
+  }
+
+  // Returns f * cos for the sampled direction, with its pdf
+  pub fn scatter(&self, r_in: &Ray, rec: &mut HitRecord) -> Option<(Vec3, Ray, f64)> {
+    // x runs along the fiber, z points back at the viewer and y is across
+    let wo_world = -r_in.direction().unit();
+    let x = if rec.tangent.squared_length() > 0.0 {
+      rec.tangent
+    } else {
+      ONB::build_from_w(rec.normal).u
+    };
+    let z = wo_world - x * scalar(wo_world.dot(x));
+    if z.squared_length() == 0.0 {
+      return None;
+    }
+    let z = z.unit();
+    let y = z.cross(x);
+
+    let wo = vec3(wo_world.dot(x), 0.0, wo_world.dot(z));
+    let h = (2.0 * rec.v - 1.0).clamp(-1.0, 1.0);
+    let (wi, f, pdf) = self.sample(wo, h);
+    if pdf <= 0.0 {
+      return None;
+    }
+
+    let direction = x * scalar(wi.x) + y * scalar(wi.y) + z * scalar(wi.z);
+    Some((f, Ray::new_at_time(rec.p, direction, r_in.time()), pdf))
+  }
+
+  fn sample(&self, wo: Vec3, h: f64) -> (Vec3, Vec3, f64) {
+    let mut rng = rand::thread_rng();
+    let sin_theta_o = wo.x;
+    let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+    let (ap, gamma_t) = self.attenuation(sin_theta_o, cos_theta_o, h);
+    let ap_pdf = lobe_pdf(&ap);
+
+    // Pick a lobe by how much light it carries
+    let mut pick = rng.gen::<f64>();
+    let mut p = 0;
+    while p < HAIR_P_MAX && pick >= ap_pdf[p] {
+      pick -= ap_pdf[p];
+      p += 1;
+    }
+    let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+
+    // Longitudinal angle from the lobe's Mp
+    let u = rng.gen::<f64>().max(1e-5);
+    let cos_theta = 1.0 + self.v[p] * (u + (1.0 - u) * (-2.0 / self.v[p]).exp()).ln();
+    let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+    let cos_phi = (2.0 * PI * rng.gen::<f64>()).cos();
+    let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+    let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+    // Azimuth from its Np
+    let dphi = if p < HAIR_P_MAX {
+      lobe_phi(p, safe_asin(h), gamma_t) + sample_trimmed_logistic(rng.gen(), self.s, -PI, PI)
+    } else {
+      2.0 * PI * rng.gen::<f64>()
+    };
+    let phi_i = wo.z.atan2(wo.y) + dphi;
+
+    let wi = vec3(
+      sin_theta_i,
+      cos_theta_i * phi_i.cos(),
+      cos_theta_i * phi_i.sin(),
+    );
+    let (f, pdf) = self.evaluate(wo, wi, h);
+    (wi, f, pdf)
+  }
+
+  // f * cos and the pdf of sample() picking wi, they share nearly everything
+  fn evaluate(&self, wo: Vec3, wi: Vec3, h: f64) -> (Vec3, f64) {
+    let sin_theta_o = wo.x;
+    let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+    let sin_theta_i = wi.x;
+    let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+    let phi = wi.z.atan2(wi.y) - wo.z.atan2(wo.y);
+
+    let gamma_o = safe_asin(h);
+    let (ap, gamma_t) = self.attenuation(sin_theta_o, cos_theta_o, h);
+    let ap_pdf = lobe_pdf(&ap);
+
+    let mut f = scalar(0.0);
+    let mut pdf = 0.0;
+    for p in 0..HAIR_P_MAX {
+      let (sin_theta_op, cos_theta_op) = self.tilted(p, sin_theta_o, cos_theta_o);
+      let lobe = mp(
+        cos_theta_i,
+        cos_theta_op,
+        sin_theta_i,
+        sin_theta_op,
+        self.v[p],
+      ) * np(phi, p, self.s, gamma_o, gamma_t);
+      f += ap[p] * scalar(lobe);
+      pdf += ap_pdf[p] * lobe;
+    }
+    let rest = mp(
+      cos_theta_i,
+      cos_theta_o,
+      sin_theta_i,
+      sin_theta_o,
+      self.v[HAIR_P_MAX],
+    ) / (2.0 * PI);
+    f += ap[HAIR_P_MAX] * scalar(rest);
+    pdf += ap_pdf[HAIR_P_MAX] * rest;
+    (f, pdf)
+  }
+
+  // How much of the light ends up in each lobe, and the refracted azimuth
+  fn attenuation(&self, sin_theta_o: f64, cos_theta_o: f64, h: f64) -> ([Vec3; 4], f64) {
+    let sin_theta_t = sin_theta_o / HAIR_ETA;
+    let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+    // Modified index of refraction for the projection across the fiber
+    let etap = (HAIR_ETA * HAIR_ETA - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+    let sin_gamma_t = h / etap;
+    let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+
+    let distance = 2.0 * cos_gamma_t / cos_theta_t;
+    let t = vec3(
+      (-self.sigma_a.x * distance).exp(),
+      (-self.sigma_a.y * distance).exp(),
+      (-self.sigma_a.z * distance).exp(),
+    );
+
+    let f = fr_dielectric(cos_theta_o * safe_sqrt(1.0 - h * h), HAIR_ETA);
+    (lobe_attenuation(f, t), safe_asin(sin_gamma_t))
+  }
+
+  // Outgoing angle shifted by the scale tilt for lobe p
+  fn tilted(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+    let (sin_2k, cos_2k) = (self.sin_2k_alpha, self.cos_2k_alpha);
+    let (sin_theta_op, cos_theta_op) = match p {
+      0 => (
+        sin_theta_o * cos_2k[1] - cos_theta_o * sin_2k[1],
+        cos_theta_o * cos_2k[1] + sin_theta_o * sin_2k[1],
+      ),
+      1 => (
+        sin_theta_o * cos_2k[0] + cos_theta_o * sin_2k[0],
+        cos_theta_o * cos_2k[0] - sin_theta_o * sin_2k[0],
+      ),
+      2 => (
+        sin_theta_o * cos_2k[2] + cos_theta_o * sin_2k[2],
+        cos_theta_o * cos_2k[2] - sin_theta_o * sin_2k[2],
+      ),
+      _ => (sin_theta_o, cos_theta_o),
+    };
+    (sin_theta_op, cos_theta_op.abs())
+  }
+}
+
+// Fresnel reflectance f and transmittance t through the fiber, per lobe
+fn lobe_attenuation(f: f64, t: Vec3) -> [Vec3; 4] {
+  let tt = scalar((1.0 - f) * (1.0 - f)) * t;
+  let trt = tt * t * scalar(f);
+  [
+    scalar(f),
+    tt,
+    trt,
+    trt * t * scalar(f) / (scalar(1.0) - t * scalar(f)),
+  ]
+}
+
+fn lobe_pdf(ap: &[Vec3; 4]) -> [f64; 4] {
+  let weights: Vec<f64> = ap.iter().map(|a| (a.x + a.y + a.z) / 3.0).collect();
+  let total: f64 = weights.iter().sum();
+  let mut pdf = [0.0; 4];
+  for (p, weight) in pdf.iter_mut().zip(weights) {
+    *p = weight / total;
+  }
+  pdf
+}
+
+// Longitudinal scattering, a spherical Gaussian around the mirror direction
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+  let a = cos_theta_i * cos_theta_o / v;
+  let b = sin_theta_i * sin_theta_o / v;
+  if v <= 0.1 {
+    // Low roughness overflows the direct form
+    (log_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+  } else {
+    (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+  }
+}
+
+// Modified Bessel function of the first kind, by its series
+fn i0(x: f64) -> f64 {
+  let mut value = 0.0;
+  let mut x2i = 1.0;
+  let mut factorial = 1.0;
+  let mut four_i = 1.0;
+  for i in 0..10 {
+    if i > 1 {
+      factorial *= i as f64;
+    }
+    value += x2i / (four_i * factorial * factorial);
+    x2i *= x * x;
+    four_i *= 4.0;
+  }
+  value
+}
+
+fn log_i0(x: f64) -> f64 {
+  if x > 12.0 {
+    x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+  } else {
+    i0(x).ln()
+  }
+}
+
+// Azimuthal scattering, a logistic around where lobe p leaves the fiber
+fn np(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+  let mut dphi = phi - lobe_phi(p, gamma_o, gamma_t);
+  while dphi > PI {
+    dphi -= 2.0 * PI;
+  }
+  while dphi < -PI {
+    dphi += 2.0 * PI;
+  }
+  trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn lobe_phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+  let p = p as f64;
+  2.0 * p * gamma_t - 2.0 * gamma_o + p * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+  let e = (-x.abs() / s).exp();
+  e / (s * (1.0 + e) * (1.0 + e))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+  1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+  logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+  let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+  let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+  x.clamp(a, b)
+}
+
+// Unpolarized Fresnel reflectance going from air into `eta`
+fn fr_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+  let cos_i = cos_theta_i.clamp(0.0, 1.0);
+  let sin_t = safe_sqrt(1.0 - cos_i * cos_i) / eta;
+  if sin_t >= 1.0 {
+    return 1.0;
+  }
+  let cos_t = safe_sqrt(1.0 - sin_t * sin_t);
+  let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+  x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f64) -> f64 {
+  x.clamp(-1.0, 1.0).asin()
+}
@@ -0,0 +1,599 @@
+use rand::prelude::*;
+
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+// Everything here stands on the xz plane at `center` and points up +y, use
+// Transform to orient them some other way.
+
+const PI: f64 = std::f64::consts::PI;
+
+fn azimuth_u(x: f64, z: f64) -> f64 {
+  let phi = z.atan2(x);
+  if phi < 0.0 {
+    (phi + 2.0 * PI) / (2.0 * PI)
+  } else {
+    phi / (2.0 * PI)
+  }
+}
+
+// Where a ray crosses the horizontal ring between `inner` and `outer` at
+// height `y` around `center`, as (t, point)
+fn ring_hit(
+  r: &Ray,
+  t_min: f64,
+  t_max: f64,
+  center: Vec3,
+  y: f64,
+  inner: f64,
+  outer: f64,
+) -> Option<(f64, Vec3)> {
+  let t = (y - r.origin().y) / r.direction().y;
+  if !(t > t_min && t < t_max) {
+    return None;
+  }
+  let p = r.point_at_parameter(t);
+  let dist_squared = (p.x - center.x).powi(2) + (p.z - center.z).powi(2);
+  if dist_squared > outer * outer || dist_squared < inner * inner {
+    return None;
+  }
+  Some((t, p))
+}
+
+fn ring_uv(p: Vec3, center: Vec3, inner: f64, outer: f64) -> (f64, f64) {
+  let dx = p.x - center.x;
+  let dz = p.z - center.z;
+  let dist = (dx * dx + dz * dz).sqrt();
+  (azimuth_u(dx, dz), (outer - dist) / (outer - inner))
+}
+
+// Uniform by area on the ring between `inner` and `outer`
+fn ring_random(center: Vec3, y: f64, inner: f64, outer: f64) -> Vec3 {
+  let mut rng = rand::thread_rng();
+  let dist = (inner * inner + rng.gen::<f64>() * (outer * outer - inner * inner)).sqrt();
+  let phi = 2.0 * PI * rng.gen::<f64>();
+  vec3(center.x + dist * phi.cos(), y, center.z + dist * phi.sin())
+}
+
+// Smallest root of a*t^2 + 2*b*t + c in (t_min, t_max) that passes `accept`
+fn nearest_root(
+  a: f64,
+  b: f64,
+  c: f64,
+  t_min: f64,
+  t_max: f64,
+  accept: impl Fn(f64) -> bool,
+) -> Option<f64> {
+  let discriminant = b * b - a * c;
+  if discriminant < 0.0 {
+    return None;
+  }
+  let sqrt_d = discriminant.sqrt();
+  let mut roots = [(-b - sqrt_d) / a, (-b + sqrt_d) / a];
+  if roots[0] > roots[1] {
+    roots.swap(0, 1);
+  }
+  roots
+    .iter()
+    .cloned()
+    .find(|&t| t > t_min && t < t_max && accept(t))
+}
+
+#[derive(Debug)]
+pub struct Disk {
+  pub center: Vec3,
+  pub inner_radius: f64,
+  pub radius: f64,
+  pub material: Material,
+}
+
+impl Disk {
+  pub fn new(center: Vec3, radius: f64, material: Material) -> Self {
+    Self::new_annulus(center, 0.0, radius, material)
+  }
+
+  pub fn new_annulus(center: Vec3, inner_radius: f64, radius: f64, material: Material) -> Self {
+    Self {
+      center,
+      inner_radius,
+      radius,
+      material,
+    }
+  }
+
+  fn area(&self) -> f64 {
+    PI * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+  }
+}
+
+impl Hitable for Disk {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let (t, p) = ring_hit(
+      r,
+      t_min,
+      t_max,
+      self.center,
+      self.center.y,
+      self.inner_radius,
+      self.radius,
+    )?;
+    let (u, v) = ring_uv(p, self.center, self.inner_radius, self.radius);
+    Some(HitRecord {
+      t,
+      u,
+      v,
+      p,
+      normal: vec3(0.0, 1.0, 0.0),
+      front_face: r.direction().y < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    ring_hit(
+      r,
+      t_min,
+      t_max,
+      self.center,
+      self.center.y,
+      self.inner_radius,
+      self.radius,
+    )
+    .is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(Aabb::new(
+      self.center - vec3(self.radius, 0.0001, self.radius),
+      self.center + vec3(self.radius, 0.0001, self.radius),
+    ))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    area_pdf_value(self, self.area(), o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    ring_random(self.center, self.center.y, self.inner_radius, self.radius) - o
+  }
+}
+
+#[derive(Debug)]
+pub struct Cylinder {
+  pub center: Vec3,
+  pub radius: f64,
+  pub height: f64,
+  pub capped: bool,
+  pub material: Material,
+}
+
+impl Cylinder {
+  pub fn new(center: Vec3, radius: f64, height: f64, capped: bool, material: Material) -> Self {
+    Self {
+      center,
+      radius,
+      height,
+      capped,
+      material,
+    }
+  }
+
+  fn side_area(&self) -> f64 {
+    2.0 * PI * self.radius * self.height
+  }
+
+  fn cap_area(&self) -> f64 {
+    if self.capped {
+      PI * self.radius * self.radius
+    } else {
+      0.0
+    }
+  }
+
+  // Nearest crossing of the tube without its caps
+  fn side_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    let oc = r.origin() - self.center;
+    let d = r.direction();
+    let a = d.x * d.x + d.z * d.z;
+    if a == 0.0 {
+      return None;
+    }
+    let b = oc.x * d.x + oc.z * d.z;
+    let c = oc.x * oc.x + oc.z * oc.z - self.radius * self.radius;
+    nearest_root(a, b, c, t_min, t_max, |t| {
+      let y = oc.y + t * d.y;
+      y >= 0.0 && y <= self.height
+    })
+  }
+
+  fn cap_hit(&self, r: &Ray, t_min: f64, t_max: f64, y: f64) -> Option<(f64, Vec3)> {
+    ring_hit(
+      r,
+      t_min,
+      t_max,
+      self.center,
+      self.center.y + y,
+      0.0,
+      self.radius,
+    )
+  }
+}
+
+impl Hitable for Cylinder {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut closest = t_max;
+    let mut rec = None;
+
+    if let Some(t) = self.side_hit(r, t_min, closest) {
+      let p = r.point_at_parameter(t);
+      let local = p - self.center;
+      closest = t;
+      rec = Some(HitRecord {
+        t,
+        u: azimuth_u(local.x, local.z),
+        v: local.y / self.height,
+        p,
+        normal: vec3(local.x, 0.0, local.z) / scalar(self.radius),
+        front_face: r.direction().dot(vec3(local.x, 0.0, local.z)) < 0.0,
+        tangent: scalar(0.0),
+        material: Some(&self.material),
+      });
+    }
+
+    if self.capped {
+      for &(y, normal_y) in &[(0.0, -1.0), (self.height, 1.0)] {
+        if let Some((t, p)) = self.cap_hit(r, t_min, closest, y) {
+          let (u, v) = ring_uv(p, self.center, 0.0, self.radius);
+          closest = t;
+          rec = Some(HitRecord {
+            t,
+            u,
+            v,
+            p,
+            normal: vec3(0.0, normal_y, 0.0),
+            front_face: r.direction().y * normal_y < 0.0,
+            tangent: scalar(0.0),
+            material: Some(&self.material),
+          });
+        }
+      }
+    }
+
+    rec
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.side_hit(r, t_min, t_max).is_some()
+      || (self.capped
+        && [0.0, self.height]
+          .iter()
+          .any(|&y| self.cap_hit(r, t_min, t_max, y).is_some()))
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(Aabb::new(
+      self.center - vec3(self.radius, 0.0, self.radius),
+      self.center + vec3(self.radius, self.height, self.radius),
+    ))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    surface_pdf_value(self, self.side_area() + 2.0 * self.cap_area(), o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let total = self.side_area() + 2.0 * self.cap_area();
+    let pick = rng.gen::<f64>() * total;
+
+    let point = if pick < self.side_area() {
+      let phi = 2.0 * PI * rng.gen::<f64>();
+      self.center
+        + vec3(
+          self.radius * phi.cos(),
+          self.height * rng.gen::<f64>(),
+          self.radius * phi.sin(),
+        )
+    } else if pick < self.side_area() + self.cap_area() {
+      ring_random(self.center, self.center.y, 0.0, self.radius)
+    } else {
+      ring_random(self.center, self.center.y + self.height, 0.0, self.radius)
+    };
+    point - o
+  }
+}
+
+// Base of `radius` at `center`, apex `height` above it
+#[derive(Debug)]
+pub struct Cone {
+  pub center: Vec3,
+  pub radius: f64,
+  pub height: f64,
+  pub capped: bool,
+  pub material: Material,
+}
+
+impl Cone {
+  pub fn new(center: Vec3, radius: f64, height: f64, capped: bool, material: Material) -> Self {
+    Self {
+      center,
+      radius,
+      height,
+      capped,
+      material,
+    }
+  }
+
+  fn side_area(&self) -> f64 {
+    PI * self.radius * (self.radius * self.radius + self.height * self.height).sqrt()
+  }
+
+  fn cap_area(&self) -> f64 {
+    if self.capped {
+      PI * self.radius * self.radius
+    } else {
+      0.0
+    }
+  }
+
+  // Nearest crossing of the slope without the base
+  fn side_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    // x^2 + z^2 = k^2 (h - y)^2, measured from the base center
+    let oc = r.origin() - self.center;
+    let d = r.direction();
+    let k2 = (self.radius / self.height).powi(2);
+    let h = self.height - oc.y;
+    let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+    let b = oc.x * d.x + oc.z * d.z + k2 * h * d.y;
+    let c = oc.x * oc.x + oc.z * oc.z - k2 * h * h;
+
+    let on_side = |t: f64| {
+      let y = oc.y + t * d.y;
+      y >= 0.0 && y <= self.height
+    };
+    if a.abs() < 1e-12 {
+      // Parallel to the slope, there's only one crossing
+      let t = -c / (2.0 * b);
+      if t > t_min && t < t_max && on_side(t) {
+        Some(t)
+      } else {
+        None
+      }
+    } else {
+      nearest_root(a, b, c, t_min, t_max, on_side)
+    }
+  }
+
+  fn cap_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+    ring_hit(
+      r,
+      t_min,
+      t_max,
+      self.center,
+      self.center.y,
+      0.0,
+      self.radius,
+    )
+  }
+}
+
+impl Hitable for Cone {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let mut closest = t_max;
+    let mut rec = None;
+
+    if let Some(t) = self.side_hit(r, t_min, t_max) {
+      let p = r.point_at_parameter(t);
+      let local = p - self.center;
+      let k2 = (self.radius / self.height).powi(2);
+      let gradient = vec3(local.x, k2 * (self.height - local.y), local.z);
+      // Right on the apex the gradient is zero, the axis is the best there is
+      let normal = if gradient.squared_length() > 0.0 {
+        gradient.unit()
+      } else {
+        vec3(0.0, 1.0, 0.0)
+      };
+      closest = t;
+      rec = Some(HitRecord {
+        t,
+        u: azimuth_u(local.x, local.z),
+        v: local.y / self.height,
+        p,
+        normal,
+        front_face: r.direction().dot(normal) < 0.0,
+        tangent: scalar(0.0),
+        material: Some(&self.material),
+      });
+    }
+
+    if self.capped {
+      if let Some((t, p)) = self.cap_hit(r, t_min, closest) {
+        let (u, v) = ring_uv(p, self.center, 0.0, self.radius);
+        rec = Some(HitRecord {
+          t,
+          u,
+          v,
+          p,
+          normal: vec3(0.0, -1.0, 0.0),
+          front_face: r.direction().y > 0.0,
+          tangent: scalar(0.0),
+          material: Some(&self.material),
+        });
+      }
+    }
+
+    rec
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.side_hit(r, t_min, t_max).is_some()
+      || (self.capped && self.cap_hit(r, t_min, t_max).is_some())
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(Aabb::new(
+      self.center - vec3(self.radius, 0.0, self.radius),
+      self.center + vec3(self.radius, self.height, self.radius),
+    ))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    surface_pdf_value(self, self.side_area() + self.cap_area(), o, v)
+  }
+
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let total = self.side_area() + self.cap_area();
+
+    let point = if rng.gen::<f64>() * total < self.side_area() {
+      // Slant distance from the apex goes as sqrt to stay uniform by area
+      let s = rng.gen::<f64>().sqrt();
+      let phi = 2.0 * PI * rng.gen::<f64>();
+      self.center
+        + vec3(
+          s * self.radius * phi.cos(),
+          (1.0 - s) * self.height,
+          s * self.radius * phi.sin(),
+        )
+    } else {
+      ring_random(self.center, self.center.y, 0.0, self.radius)
+    };
+    point - o
+  }
+}
+
+// pbrt's partial sphere, cut off below z_min and above z_max (measured from
+// the center) and swept phi_max degrees around z from +x. Unlike the rest of
+// the file it keeps pbrt's z up, so wrap it in a Transform to stand it up +y.
+// It's open, so the inside can be hit too.
+#[derive(Debug)]
+pub struct PartialSphere {
+  pub center: Vec3,
+  pub radius: f64,
+  pub z_min: f64,
+  pub z_max: f64,
+  pub phi_max: f64,
+  pub material: Material,
+  theta_z_min: f64,
+  theta_z_max: f64,
+}
+
+impl PartialSphere {
+  pub fn new(
+    center: Vec3,
+    radius: f64,
+    z_min: f64,
+    z_max: f64,
+    phi_max: f64,
+    material: Material,
+  ) -> Self {
+    let z_min = z_min.clamp(-radius, radius);
+    let z_max = z_max.clamp(-radius, radius);
+    Self {
+      center,
+      radius,
+      z_min,
+      z_max,
+      phi_max: phi_max.clamp(0.0, 360.0).to_radians(),
+      material,
+      theta_z_min: (z_min / radius).acos(),
+      theta_z_max: (z_max / radius).acos(),
+    }
+  }
+
+  // A zone of a sphere has the area of the cylinder around it
+  fn area(&self) -> f64 {
+    self.phi_max * self.radius * (self.z_max - self.z_min)
+  }
+
+  fn phi(local: Vec3) -> f64 {
+    azimuth_u(local.x, local.y) * 2.0 * PI
+  }
+
+  fn contains(&self, local: Vec3) -> bool {
+    local.z >= self.z_min && local.z <= self.z_max && Self::phi(local) <= self.phi_max
+  }
+
+  fn nearest(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    let oc = r.origin() - self.center;
+    let d = r.direction();
+    nearest_root(
+      d.dot(d),
+      oc.dot(d),
+      oc.dot(oc) - self.radius * self.radius,
+      t_min,
+      t_max,
+      |t| self.contains(oc + scalar(t) * d),
+    )
+  }
+}
+
+impl Hitable for PartialSphere {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let t = self.nearest(r, t_min, t_max)?;
+    let p = r.point_at_parameter(t);
+    let local = p - self.center;
+    let normal = local / scalar(self.radius);
+    let theta = normal.z.clamp(-1.0, 1.0).acos();
+    Some(HitRecord {
+      t,
+      u: Self::phi(local) / self.phi_max,
+      v: (theta - self.theta_z_min) / (self.theta_z_max - self.theta_z_min),
+      p,
+      normal,
+      front_face: r.direction().dot(normal) < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.nearest(r, t_min, t_max).is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(Aabb::new(
+      self.center + vec3(-self.radius, -self.radius, self.z_min),
+      self.center + vec3(self.radius, self.radius, self.z_max),
+    ))
+  }
+
+  fn pdf_value(&self, o: Vec3, v: Vec3) -> f64 {
+    surface_pdf_value(self, self.area(), o, v)
+  }
+
+  // Height is uniform by area on a sphere, so both coordinates are too
+  fn random(&self, o: Vec3) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let z = self.z_min + rng.gen::<f64>() * (self.z_max - self.z_min);
+    let phi = self.phi_max * rng.gen::<f64>();
+    let ring = (self.radius * self.radius - z * z).max(0.0).sqrt();
+    self.center + vec3(ring * phi.cos(), ring * phi.sin(), z) - o
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cone_apex_normal_is_the_axis() {
+    let cone = Cone::new(
+      scalar(0.0),
+      1.0,
+      2.0,
+      false,
+      Lambertian::new_from_color(scalar(0.5)),
+    );
+    let r = Ray::new(vec3(0.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0));
+    let rec = cone
+      .hit(&r, 0.001, f64::MAX)
+      .expect("ray down the axis misses the apex");
+    assert_eq!(rec.t, 3.0);
+    assert_eq!(rec.normal, vec3(0.0, 1.0, 0.0));
+  }
+}
@@ -0,0 +1,160 @@
+use std::ops::Mul;
+
+use crate::mat4::Mat4;
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+  pub w: f64,
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+}
+
+impl Default for Quat {
+  fn default() -> Self {
+    Self::identity()
+  }
+}
+
+impl Quat {
+  pub fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+    Self { w, x, y, z }
+  }
+
+  pub fn identity() -> Self {
+    Self::new(1.0, 0.0, 0.0, 0.0)
+  }
+
+  // Angle in degrees, like the rest of the rotations
+  pub fn from_axis_angle(axis: Vec3, angle: f64) -> Self {
+    let a = axis.unit();
+    let (sin_half, cos_half) = (angle.to_radians() / 2.0).sin_cos();
+    Self::new(cos_half, a.x * sin_half, a.y * sin_half, a.z * sin_half)
+  }
+
+  pub fn dot(&self, other: Self) -> f64 {
+    self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+  }
+
+  pub fn length(&self) -> f64 {
+    self.dot(*self).sqrt()
+  }
+
+  pub fn unit(&self) -> Self {
+    let inv = 1.0 / self.length();
+    Self::new(self.w * inv, self.x * inv, self.y * inv, self.z * inv)
+  }
+
+  pub fn slerp(self, other: Self, t: f64) -> Self {
+    // q and -q are the same rotation, take the short way around
+    let mut cos_theta = self.dot(other);
+    let other = if cos_theta < 0.0 {
+      cos_theta = -cos_theta;
+      Self::new(-other.w, -other.x, -other.y, -other.z)
+    } else {
+      other
+    };
+
+    let (a, b) = if cos_theta > 0.9995 {
+      // Nearly parallel, sin(theta) is too small to divide by
+      (1.0 - t, t)
+    } else {
+      let theta = cos_theta.acos();
+      let sin_theta = theta.sin();
+      (
+        ((1.0 - t) * theta).sin() / sin_theta,
+        (t * theta).sin() / sin_theta,
+      )
+    };
+
+    Self::new(
+      a * self.w + b * other.w,
+      a * self.x + b * other.x,
+      a * self.y + b * other.y,
+      a * self.z + b * other.z,
+    )
+    .unit()
+  }
+
+  pub fn to_mat4(self) -> Mat4 {
+    let Self { w, x, y, z } = self;
+    Mat4::new([
+      [
+        1.0 - 2.0 * (y * y + z * z),
+        2.0 * (x * y - w * z),
+        2.0 * (x * z + w * y),
+        0.0,
+      ],
+      [
+        2.0 * (x * y + w * z),
+        1.0 - 2.0 * (x * x + z * z),
+        2.0 * (y * z - w * x),
+        0.0,
+      ],
+      [
+        2.0 * (x * z - w * y),
+        2.0 * (y * z + w * x),
+        1.0 - 2.0 * (x * x + y * y),
+        0.0,
+      ],
+      [0.0, 0.0, 0.0, 1.0],
+    ])
+  }
+}
+
+impl Mul for Quat {
+  type Output = Self;
+
+  fn mul(self, other: Self) -> Self {
+    Self::new(
+      self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+      self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+      self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+      self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vec3::*;
+
+  fn assert_close(a: Quat, b: Quat) {
+    assert!(
+      (a.w - b.w).abs() < 1e-12
+        && (a.x - b.x).abs() < 1e-12
+        && (a.y - b.y).abs() < 1e-12
+        && (a.z - b.z).abs() < 1e-12,
+      "{:?} != {:?}",
+      a,
+      b
+    );
+  }
+
+  #[test]
+  fn slerp_endpoints() {
+    let q0 = Quat::from_axis_angle(vec3(0.0, 1.0, 0.0), 30.0);
+    let q1 = Quat::from_axis_angle(vec3(1.0, 1.0, 0.0), 120.0);
+    assert_close(q0.slerp(q1, 0.0), q0);
+    assert_close(q0.slerp(q1, 1.0), q1);
+    // Nearly the same rotation goes down the lerp branch
+    let q2 = Quat::from_axis_angle(vec3(0.0, 1.0, 0.0), 31.0);
+    assert_close(q0.slerp(q2, 0.0), q0);
+    assert_close(q0.slerp(q2, 1.0), q2);
+  }
+
+  #[test]
+  fn slerp_takes_the_short_way() {
+    // -q1 is q1's rotation, so the end is q1 negated back
+    let q0 = Quat::identity();
+    let q1 = Quat::from_axis_angle(vec3(0.0, 0.0, 1.0), 90.0);
+    let minus_q1 = Quat::new(-q1.w, -q1.x, -q1.y, -q1.z);
+    assert_close(q0.slerp(minus_q1, 1.0), q1);
+    assert_close(
+      q0.slerp(minus_q1, 0.5),
+      Quat::from_axis_angle(vec3(0.0, 0.0, 1.0), 45.0),
+    );
+  }
+}
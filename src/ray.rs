@@ -3,11 +3,22 @@ use crate::vec3::*;
 pub struct Ray {
   pub a: Vec3,
   pub b: Vec3,
+  pub time: f64,
+  // Box tests divide by the direction on every node, so do it once here
+  inv_direction: Vec3,
 }
 
 impl Ray {
   pub fn new(a: Vec3, b: Vec3) -> Self {
-    Self { a, b }
+    Self::new_at_time(a, b, 0.0)
+  }
+  pub fn new_at_time(a: Vec3, b: Vec3, time: f64) -> Self {
+    Self {
+      a,
+      b,
+      time,
+      inv_direction: vec3(1.0 / b.x, 1.0 / b.y, 1.0 / b.z),
+    }
   }
   pub fn origin(&self) -> Vec3 {
     self.a
@@ -15,6 +26,12 @@ impl Ray {
   pub fn direction(&self) -> Vec3 {
     self.b
   }
+  pub fn inv_direction(&self) -> Vec3 {
+    self.inv_direction
+  }
+  pub fn time(&self) -> f64 {
+    self.time
+  }
   pub fn point_at_parameter(&self, t: f64) -> Vec3 {
     self.a + self.b * scalar(t)
   }
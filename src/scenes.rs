@@ -1,11 +1,24 @@
 use rand::prelude::*;
+use std::sync::Arc;
 
+use crate::animation::*;
 use crate::bvh::*;
 use crate::camera::Camera;
+use crate::csg::*;
+use crate::curve::*;
+use crate::heightfield::Heightfield;
 use crate::hitable::*;
+use crate::mat4::Mat4;
 use crate::material::*;
+use crate::perlin::Perlin;
+use crate::quadric::PartialSphere;
+use crate::quat::Quat;
+use crate::random_in_unit_sphere;
+use crate::random_on_unit_sphere;
 use crate::ray::Ray;
+use crate::sdf::*;
 use crate::texture::*;
+use crate::tlas::Tlas;
 use crate::vec3::*;
 
 /*
@@ -279,42 +292,50 @@ pub fn cornell_box_scene(ratio: f64) -> (Camera, HitableList) {
   let green = Lambertian::new(Texture::new_constant(vec3(0.12, 0.45, 0.15)));
   let light = DiffuseLight::new(Texture::new_constant(scalar(15.0)));
 
+  // Edge order sets the normal, all of these face into the room
   let list: Vec<Box<dyn Hitable>> = vec![
     // Left wall
-    Box::new(FlipNormals::new_yz(YZRect::new(
-      0.0, 555.0, 0.0, 565.0, 555.0, green,
-    ))),
+    Box::new(Quad::new(
+      vec3(555.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 565.0),
+      vec3(0.0, 555.0, 0.0),
+      green,
+    )),
     // Right wall
-    Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)),
+    Box::new(Quad::new(
+      vec3(0.0, 0.0, 0.0),
+      vec3(0.0, 555.0, 0.0),
+      vec3(0.0, 0.0, 555.0),
+      red,
+    )),
     // Light
-    Box::new(FlipNormals::new_xz(XZRect::new(
-      213.0,
-      343.0,
-      227.0,
-      332.0,
-      554.0,
+    Box::new(Quad::new(
+      vec3(213.0, 554.0, 227.0),
+      vec3(130.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 105.0),
       light.clone(),
-    ))),
+    )),
     // Ceiling
-    Box::new(FlipNormals::new_xz(XZRect::new(
-      0.0,
-      555.0,
-      0.0,
-      555.0,
-      555.0,
+    Box::new(Quad::new(
+      vec3(0.0, 555.0, 0.0),
+      vec3(555.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 555.0),
       white.clone(),
-    ))),
+    )),
     // Floor
-    Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())),
+    Box::new(Quad::new(
+      vec3(0.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 555.0),
+      vec3(555.0, 0.0, 0.0),
+      white.clone(),
+    )),
     // Back wall
-    Box::new(FlipNormals::new_xy(XYRect::new(
-      0.0,
-      555.0,
-      0.0,
-      555.0,
-      555.0,
+    Box::new(Quad::new(
+      vec3(0.0, 0.0, 555.0),
+      vec3(0.0, 555.0, 0.0),
+      vec3(555.0, 0.0, 0.0),
       white.clone(),
-    ))),
+    )),
     // Cuboids
     Box::new(Translate::new(
       Box::new(RotateY::new(
@@ -354,11 +375,412 @@ pub fn cornell_box_scene(ratio: f64) -> (Camera, HitableList) {
       ratio,
       aperture,
       dist_to_focus,
+      0.0,
+      1.0,
     ),
     list,
   )
 }
 
+// The ceiling light from cornell_box_scene, for sampling. The other cornell
+// scenes build on that one so they share it.
+pub fn cornell_box_lights() -> HitableList {
+  let light = DiffuseLight::new(Texture::new_constant(scalar(15.0)));
+  HitableList::new(vec![Box::new(Quad::new(
+    vec3(213.0, 554.0, 227.0),
+    vec3(130.0, 0.0, 0.0),
+    vec3(0.0, 0.0, 105.0),
+    light,
+  ))])
+}
+
+pub fn cornell_motion_blur_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+
+  // Rises a little while the shutter is open, on top of the short box
+  list.list.push(Box::new(MovingSphere::new(
+    vec3(212.5, 215.0, 147.5),
+    vec3(212.5, 265.0, 147.5),
+    camera.time0,
+    camera.time1,
+    50.0,
+    Lambertian::new_from_color(vec3(0.1, 0.2, 0.5)),
+  )));
+
+  (camera, list)
+}
+
+pub fn cornell_animation_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+
+  // A small box tumbling across the front of the room over two seconds
+  let cuboid = Box::new(Cuboid::new(
+    scalar(-40.0),
+    scalar(40.0),
+    Lambertian::new_from_color(vec3(0.1, 0.2, 0.5)),
+  ));
+  let axis = vec3(1.0, 1.0, 0.0);
+  list.list.push(Box::new(Animated::new(
+    cuboid,
+    vec![
+      Keyframe::new(
+        0.0,
+        vec3(120.0, 300.0, 100.0),
+        Quat::identity(),
+        scalar(1.0),
+      ),
+      Keyframe::new(
+        1.0,
+        vec3(278.0, 380.0, 100.0),
+        Quat::from_axis_angle(axis, 120.0),
+        vec3(1.0, 1.5, 1.0),
+      ),
+      Keyframe::new(
+        2.0,
+        vec3(435.0, 300.0, 100.0),
+        Quat::from_axis_angle(axis, 240.0),
+        scalar(1.0),
+      ),
+    ],
+    Interpolation::Linear,
+  )));
+
+  (camera, list)
+}
+
+pub fn cornell_csg_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+  let blue = Lambertian::new_from_color(vec3(0.1, 0.2, 0.5));
+  let yellow = Lambertian::new_from_color(vec3(0.7, 0.6, 0.1));
+
+  // A ball with a notch cut out of the top front, sat on the short box
+  list.list.push(Box::new(Csg::difference(
+    Box::new(Sphere::new(vec3(212.5, 245.0, 147.5), 80.0, blue.clone())),
+    Box::new(Cuboid::new(
+      vec3(212.5, 245.0, 0.0),
+      vec3(320.0, 340.0, 147.5),
+      yellow,
+    )),
+  )));
+
+  // A lens from where two big spheres overlap, above the tall box
+  list.list.push(Box::new(Csg::intersection(
+    Box::new(Sphere::new(vec3(370.0, 430.0, 150.0), 150.0, blue.clone())),
+    Box::new(Sphere::new(vec3(370.0, 430.0, 400.0), 150.0, blue)),
+  )));
+
+  (camera, list)
+}
+
+pub fn cornell_sdf_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+  let blue = Lambertian::new_from_color(vec3(0.1, 0.2, 0.5));
+
+  // A blob of a ball melting into a bar, floating above the short box
+  let blob = Sdf::new_smooth_union(
+    Sdf::new_sphere(45.0),
+    Sdf::new_capsule(vec3(-70.0, -30.0, 0.0), vec3(70.0, 30.0, 0.0), 15.0),
+    40.0,
+  );
+  list.list.push(Box::new(SdfObject::new(
+    Sdf::new_translate(blob, vec3(212.5, 280.0, 147.5)),
+    blue.clone(),
+  )));
+
+  // A twisted column with a hole bored through, on top of the tall box
+  let column = Sdf::new_subtraction(
+    Sdf::new_twist(Sdf::new_cuboid(vec3(40.0, 90.0, 40.0)), 0.02),
+    Sdf::new_capsule(vec3(-60.0, 0.0, 0.0), vec3(60.0, 0.0, 0.0), 20.0),
+  );
+  list.list.push(Box::new(SdfObject::new(
+    Sdf::new_translate(column, vec3(347.5, 420.0, 377.5)),
+    blue.clone(),
+  )));
+
+  // A row of rings along the floor at the front
+  let rings = Sdf::new_repeat(
+    Sdf::new_torus(25.0, 8.0),
+    vec3(80.0, 1.0, 1.0),
+    vec3(2.0, 0.0, 0.0),
+  );
+  list.list.push(Box::new(SdfObject::new(
+    Sdf::new_translate(rings, vec3(278.0, 8.0, 30.0)),
+    blue,
+  )));
+
+  (camera, list)
+}
+
+pub fn cornell_partial_sphere_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+  let blue = Lambertian::new_from_color(vec3(0.1, 0.2, 0.5));
+  let yellow = Lambertian::new_from_color(vec3(0.7, 0.6, 0.1));
+
+  // A bowl on the short box, turned so the sphere's z is up
+  let bowl = PartialSphere::new(scalar(0.0), 80.0, -80.0, 0.0, 360.0, blue);
+  list.list.push(Box::new(
+    Transform::rotate_x(Box::new(bowl), -90.0).then(Mat4::translation(vec3(212.5, 245.0, 147.5))),
+  ));
+
+  // A ball on the tall box with the quarter facing the camera cut away, which
+  // turning z down brings round to the front
+  let ball = PartialSphere::new(scalar(0.0), 60.0, -60.0, 60.0, 270.0, yellow);
+  list.list.push(Box::new(
+    Transform::rotate_x(Box::new(ball), 90.0).then(Mat4::translation(vec3(347.5, 390.0, 377.5))),
+  ));
+
+  (camera, list)
+}
+
+pub fn cornell_instances_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+  let white = Lambertian::new_from_color(scalar(0.73));
+
+  // One ring of beads built once, then placed around the front of the room
+  let beads = 32;
+  let ring = (0..beads)
+    .map(|i| {
+      let angle = 2.0 * std::f64::consts::PI * i as f64 / beads as f64;
+      Box::new(Sphere::new(
+        vec3(40.0 * angle.cos(), 0.0, 40.0 * angle.sin()),
+        6.0,
+        white.clone(),
+      )) as Box<dyn Hitable>
+    })
+    .collect();
+  let ring: Arc<dyn Hitable> = Arc::new(FlatBvh::from_list(
+    HitableList::new(ring),
+    camera.time0,
+    camera.time1,
+  ));
+
+  let colors = [
+    vec3(0.1, 0.2, 0.5),
+    vec3(0.7, 0.6, 0.1),
+    vec3(0.2, 0.5, 0.3),
+  ];
+  let instances = (0..9)
+    .map(|i| {
+      let position = vec3(
+        100.0 + 45.0 * i as f64,
+        if i % 2 == 0 { 60.0 } else { 120.0 },
+        30.0,
+      );
+      let matrix =
+        Mat4::translation(position) * Mat4::rotation(vec3(1.0, 0.0, 0.5), 20.0 * i as f64);
+      Instance::new(Arc::clone(&ring), matrix)
+        .with_material(Lambertian::new_from_color(colors[i % colors.len()]))
+    })
+    .collect();
+  list
+    .list
+    .push(Box::new(Tlas::new(instances, camera.time0, camera.time1)));
+
+  (camera, list)
+}
+
+pub fn heightfield_scene(ratio: f64) -> (Camera, HitableList) {
+  let noise = Perlin::new();
+  let ground = Lambertian::new_from_color(vec3(0.35, 0.45, 0.25));
+  let light = DiffuseLight::new(Texture::new_constant(scalar(4.0)));
+
+  let terrain = Heightfield::from_fn(
+    256,
+    256,
+    vec3(-500.0, 0.0, -500.0),
+    vec3(1000.0, 220.0, 1000.0),
+    |u, v| noise.turb(vec3(4.0 * u, 0.5, 4.0 * v), 5),
+    ground,
+  );
+
+  let list: Vec<Box<dyn Hitable>> = vec![
+    Box::new(terrain),
+    // Overcast sky, facing down
+    Box::new(Quad::new(
+      vec3(-2000.0, 1500.0, -2000.0),
+      vec3(4000.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 4000.0),
+      light,
+    )),
+  ];
+
+  let list = HitableList::new(list);
+
+  let lookfrom = vec3(0.0, 450.0, -900.0);
+  let lookat = vec3(0.0, 50.0, 0.0);
+
+  let dist_to_focus = 10.0;
+  let aperture = 0.0;
+  let vfov = 45.0;
+
+  (
+    Camera::new(
+      lookfrom,
+      lookat,
+      vec3(0.0, 1.0, 0.0),
+      vfov,
+      ratio,
+      aperture,
+      dist_to_focus,
+      0.0,
+      1.0,
+    ),
+    list,
+  )
+}
+
+pub fn heightfield_lights() -> HitableList {
+  let light = DiffuseLight::new(Texture::new_constant(scalar(4.0)));
+  HitableList::new(vec![Box::new(Quad::new(
+    vec3(-2000.0, 1500.0, -2000.0),
+    vec3(4000.0, 0.0, 0.0),
+    vec3(0.0, 0.0, 4000.0),
+    light,
+  ))])
+}
+
+// Spheres on a checkered floor that goes on to the horizon, with a wall
+// turned to face the camera behind them. The checker is 3D and zero at y = 0,
+// hence the floor a little lower.
+pub fn plane_scene(ratio: f64) -> (Camera, HitableList) {
+  let checker = Lambertian::new(Texture::new_checker(
+    Texture::new_constant(vec3(0.2, 0.3, 0.1)),
+    Texture::new_constant(vec3(0.9, 0.9, 0.9)),
+  ));
+  let wall = Lambertian::new_from_color(vec3(0.6, 0.5, 0.4));
+  let light = DiffuseLight::new(Texture::new_constant(scalar(4.0)));
+
+  let list: Vec<Box<dyn Hitable>> = vec![
+    Box::new(Plane::new(
+      vec3(0.0, -0.15, 0.0),
+      vec3(0.0, 1.0, 0.0),
+      checker,
+    )),
+    Box::new(RotateY::new(
+      Box::new(Plane::new(vec3(0.0, 0.0, -20.0), vec3(0.0, 0.0, 1.0), wall)),
+      -30.0,
+    )),
+    Box::new(Sphere::new(
+      vec3(-2.5, 0.85, 0.0),
+      1.0,
+      Lambertian::new_from_color(vec3(0.7, 0.2, 0.2)),
+    )),
+    Box::new(Sphere::new(
+      vec3(0.0, 0.85, 0.0),
+      1.0,
+      Lambertian::new_from_color(vec3(0.2, 0.4, 0.7)),
+    )),
+    Box::new(Sphere::new(
+      vec3(2.5, 0.85, 0.0),
+      1.0,
+      Lambertian::new(Texture::new_noise(4.0)),
+    )),
+    Box::new(Quad::new(
+      vec3(-10.0, 15.0, -10.0),
+      vec3(20.0, 0.0, 0.0),
+      vec3(0.0, 0.0, 20.0),
+      light,
+    )),
+  ];
+
+  let list = HitableList::new(list);
+
+  let lookfrom = vec3(0.0, 3.0, 12.0);
+  let lookat = vec3(0.0, 1.0, 0.0);
+
+  let dist_to_focus = 10.0;
+  let aperture = 0.0;
+  let vfov = 40.0;
+
+  (
+    Camera::new(
+      lookfrom,
+      lookat,
+      vec3(0.0, 1.0, 0.0),
+      vfov,
+      ratio,
+      aperture,
+      dist_to_focus,
+      0.0,
+      1.0,
+    ),
+    list,
+  )
+}
+
+pub fn plane_lights() -> HitableList {
+  let light = DiffuseLight::new(Texture::new_constant(scalar(4.0)));
+  HitableList::new(vec![Box::new(Quad::new(
+    vec3(-10.0, 15.0, -10.0),
+    vec3(20.0, 0.0, 0.0),
+    vec3(0.0, 0.0, 20.0),
+    light,
+  ))])
+}
+
+pub fn cornell_hair_scene(ratio: f64) -> (Camera, HitableList) {
+  let (camera, mut list) = cornell_box_scene(ratio);
+  let mut rng = rand::thread_rng();
+  let skin = Lambertian::new_from_color(vec3(0.4, 0.3, 0.2));
+  let hair = Hair::new_from_melanin(1.3, 0.0, 0.3, 0.3, 2.0);
+  let grass = Lambertian::new_from_color(vec3(0.2, 0.5, 0.1));
+
+  // A furry ball sat on the short box
+  let center = vec3(212.5, 215.0, 147.5);
+  let radius = 50.0;
+  list.list.push(Box::new(Sphere::new(center, radius, skin)));
+  for _ in 0..1500 {
+    let n = random_on_unit_sphere();
+    if n.y < -0.6 {
+      continue;
+    }
+    let length = 30.0 + 15.0 * rng.gen::<f64>();
+    let root = center + scalar(radius) * n;
+    let droop = vec3(0.0, -0.35, 0.0) + scalar(0.2) * random_in_unit_sphere();
+    let points = [
+      root,
+      root + scalar(length / 3.0) * n,
+      root + scalar(2.0 * length / 3.0) * n + scalar(0.3 * length) * droop,
+      root + scalar(length) * (n + droop),
+    ];
+    list.list.extend(Curve::split(
+      points,
+      1.2,
+      0.3,
+      CurveType::Ribbon,
+      hair.clone(),
+      3,
+    ));
+  }
+
+  // A patch of grass in front of the tall box
+  for _ in 0..400 {
+    let root = vec3(
+      330.0 + 150.0 * rng.gen::<f64>(),
+      0.0,
+      40.0 + 100.0 * rng.gen::<f64>(),
+    );
+    let height = 30.0 + 30.0 * rng.gen::<f64>();
+    let lean = vec3(rng.gen::<f64>() - 0.5, 0.0, rng.gen::<f64>() - 0.5);
+    let points = [
+      root,
+      root + vec3(0.0, height / 3.0, 0.0),
+      root + vec3(0.0, 2.0 * height / 3.0, 0.0) + scalar(0.3 * height) * lean,
+      root + vec3(0.0, 0.9 * height, 0.0) + scalar(0.8 * height) * lean,
+    ];
+    list.list.extend(Curve::split(
+      points,
+      3.0,
+      0.2,
+      CurveType::Cylinder,
+      grass.clone(),
+      2,
+    ));
+  }
+
+  (camera, list)
+}
+
 /*
 pub fn cornell_smoke_scene(ratio: f64) -> (Camera, HitableList) {
   let red = Lambertian::new(Texture::new_constant(vec3(0.65, 0.05, 0.05)));
@@ -383,33 +805,33 @@ pub fn cornell_smoke_scene(ratio: f64) -> (Camera, HitableList) {
 
   let list: Vec<Box<dyn Hitable>> = vec![
     // Left wall
-    Box::new(FlipNormals::new_yz(YZRect::new(
+    Box::new(FlipNormals::new(Box::new(YZRect::new(
       0.0, 555.0, 0.0, 565.0, 555.0, green,
-    ))),
+    )))),
     // Right wall
     Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)),
     // Light
     Box::new(XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, light)),
     // Ceiling
-    Box::new(FlipNormals::new_xz(XZRect::new(
+    Box::new(FlipNormals::new(Box::new(XZRect::new(
       0.0,
       555.0,
       0.0,
       555.0,
       555.0,
       white.clone(),
-    ))),
+    )))),
     // Floor
     Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())),
     // Back wall
-    Box::new(FlipNormals::new_xy(XYRect::new(
+    Box::new(FlipNormals::new(Box::new(XYRect::new(
       0.0,
       555.0,
       0.0,
       555.0,
       555.0,
       white.clone(),
-    ))),
+    )))),
     //    Box::new(ConstantMedium::new(b1, 0.005, Texture::new_constant(scalar(0.5)))),
     //Box::new(ConstantMedium::new(
     //  b2,
@@ -0,0 +1,357 @@
+use crate::bvh::*;
+use crate::hitable::{get_sphere_uv, HitRecord, Hitable};
+use crate::material::*;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+const MAX_STEPS: usize = 512;
+// Surface tolerance, as a fraction of the bounding box diagonal
+const RELATIVE_EPSILON: f64 = 1e-5;
+
+// Distance functions centered on the origin, mostly after Inigo Quilez's
+// articles. Use Translate inside the tree or Transform around the SdfObject
+// to place them.
+#[derive(Debug, Clone)]
+pub enum Sdf {
+  Sphere {
+    radius: f64,
+  },
+  Cuboid {
+    half_size: Vec3,
+  },
+  // Ring in the xz plane, like the Torus primitive
+  Torus {
+    major_radius: f64,
+    minor_radius: f64,
+  },
+  Capsule {
+    a: Vec3,
+    b: Vec3,
+    radius: f64,
+  },
+  SmoothUnion {
+    a: Box<Sdf>,
+    b: Box<Sdf>,
+    k: f64,
+  },
+  // a with b carved out
+  Subtraction {
+    a: Box<Sdf>,
+    b: Box<Sdf>,
+  },
+  // Copies every `period`, `count` of them out each way along each axis
+  Repeat {
+    inner: Box<Sdf>,
+    period: Vec3,
+    count: Vec3,
+  },
+  // Rotates around y by `rate` radians per unit of height
+  Twist {
+    inner: Box<Sdf>,
+    rate: f64,
+  },
+  Translate {
+    inner: Box<Sdf>,
+    offset: Vec3,
+  },
+}
+
+use Sdf::*;
+
+impl Sdf {
+  pub fn new_sphere(radius: f64) -> Self {
+    Sphere { radius }
+  }
+
+  pub fn new_cuboid(half_size: Vec3) -> Self {
+    Cuboid { half_size }
+  }
+
+  pub fn new_torus(major_radius: f64, minor_radius: f64) -> Self {
+    Torus {
+      major_radius,
+      minor_radius,
+    }
+  }
+
+  pub fn new_capsule(a: Vec3, b: Vec3, radius: f64) -> Self {
+    Capsule { a, b, radius }
+  }
+
+  pub fn new_smooth_union(a: Self, b: Self, k: f64) -> Self {
+    SmoothUnion {
+      a: Box::new(a),
+      b: Box::new(b),
+      k,
+    }
+  }
+
+  pub fn new_subtraction(a: Self, b: Self) -> Self {
+    Subtraction {
+      a: Box::new(a),
+      b: Box::new(b),
+    }
+  }
+
+  pub fn new_repeat(inner: Self, period: Vec3, count: Vec3) -> Self {
+    Repeat {
+      inner: Box::new(inner),
+      period,
+      count,
+    }
+  }
+
+  pub fn new_twist(inner: Self, rate: f64) -> Self {
+    Twist {
+      inner: Box::new(inner),
+      rate,
+    }
+  }
+
+  pub fn new_translate(inner: Self, offset: Vec3) -> Self {
+    Translate {
+      inner: Box::new(inner),
+      offset,
+    }
+  }
+
+  pub fn distance(&self, p: Vec3) -> f64 {
+    match self {
+      Sphere { radius } => p.length() - radius,
+      Cuboid { half_size } => {
+        let q = vec3(p.x.abs(), p.y.abs(), p.z.abs()) - *half_size;
+        let outside = vec3(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        outside + q.x.max(q.y).max(q.z).min(0.0)
+      }
+      Torus {
+        major_radius,
+        minor_radius,
+      } => {
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+        (ring * ring + p.y * p.y).sqrt() - minor_radius
+      }
+      Capsule { a, b, radius } => {
+        let pa = p - *a;
+        let ba = *b - *a;
+        let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+        (pa - ba * scalar(h)).length() - radius
+      }
+      SmoothUnion { a, b, k } => {
+        let d1 = a.distance(p);
+        let d2 = b.distance(p);
+        let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - k * h * (1.0 - h)
+      }
+      Subtraction { a, b } => a.distance(p).max(-b.distance(p)),
+      Repeat {
+        inner,
+        period,
+        count,
+      } => {
+        let mut q = p;
+        for c in 0..3 {
+          let cell = (p[c] / period[c]).round().max(-count[c]).min(count[c]);
+          q[c] -= period[c] * cell;
+        }
+        inner.distance(q)
+      }
+      Twist { inner, rate } => inner.distance(twist(p, *rate)),
+      Translate { inner, offset } => inner.distance(p - *offset),
+    }
+  }
+
+  // Conservative box around the surface
+  pub fn bounds(&self) -> Aabb {
+    match self {
+      Sphere { radius } => Aabb::new(-scalar(*radius), scalar(*radius)),
+      Cuboid { half_size } => Aabb::new(-*half_size, *half_size),
+      Torus {
+        major_radius,
+        minor_radius,
+      } => {
+        let extent = vec3(
+          major_radius + minor_radius,
+          *minor_radius,
+          major_radius + minor_radius,
+        );
+        Aabb::new(-extent, extent)
+      }
+      Capsule { a, b, radius } => {
+        let min = vec3(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = vec3(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        Aabb::new(min - scalar(*radius), max + scalar(*radius))
+      }
+      SmoothUnion { a, b, k } => {
+        // The blend can push the surface out by at most k / 4
+        let boxy = surrounding_box(a.bounds(), b.bounds());
+        Aabb::new(boxy.min - scalar(k / 4.0), boxy.max + scalar(k / 4.0))
+      }
+      Subtraction { a, .. } => a.bounds(),
+      Repeat {
+        inner,
+        period,
+        count,
+      } => {
+        let boxy = inner.bounds();
+        Aabb::new(boxy.min - *period * *count, boxy.max + *period * *count)
+      }
+      Twist { inner, .. } => {
+        let boxy = inner.bounds();
+        let radius = xz_radius(&boxy);
+        Aabb::new(
+          vec3(-radius, boxy.min.y, -radius),
+          vec3(radius, boxy.max.y, radius),
+        )
+      }
+      Translate { inner, offset } => {
+        let boxy = inner.bounds();
+        Aabb::new(boxy.min + *offset, boxy.max + *offset)
+      }
+    }
+  }
+
+  // How much faster than 1:1 the field can change. Twisting stretches space
+  // so it has to be stepped through more carefully.
+  pub fn lipschitz(&self) -> f64 {
+    match self {
+      SmoothUnion { a, b, .. } | Subtraction { a, b } => a.lipschitz().max(b.lipschitz()),
+      Repeat { inner, .. } | Translate { inner, .. } => inner.lipschitz(),
+      Twist { inner, rate } => {
+        let stretch = rate * xz_radius(&inner.bounds());
+        inner.lipschitz() * (1.0 + stretch * stretch).sqrt()
+      }
+      _ => 1.0,
+    }
+  }
+}
+
+fn twist(p: Vec3, rate: f64) -> Vec3 {
+  let (s, c) = (rate * p.y).sin_cos();
+  vec3(c * p.x - s * p.z, p.y, s * p.x + c * p.z)
+}
+
+fn xz_radius(boxy: &Aabb) -> f64 {
+  let x = boxy.min.x.abs().max(boxy.max.x.abs());
+  let z = boxy.min.z.abs().max(boxy.max.z.abs());
+  (x * x + z * z).sqrt()
+}
+
+// Sphere traced inside the box from Sdf::bounds
+#[derive(Debug)]
+pub struct SdfObject {
+  pub sdf: Sdf,
+  pub material: Material,
+  boxy: Aabb,
+  lipschitz: f64,
+  epsilon: f64,
+}
+
+impl SdfObject {
+  pub fn new(sdf: Sdf, material: Material) -> Self {
+    let boxy = sdf.bounds();
+    Self {
+      lipschitz: sdf.lipschitz(),
+      epsilon: RELATIVE_EPSILON * (boxy.max - boxy.min).length(),
+      boxy,
+      sdf,
+      material,
+    }
+  }
+
+  fn normal(&self, p: Vec3) -> Vec3 {
+    let h = self.epsilon;
+    let dx = vec3(h, 0.0, 0.0);
+    let dy = vec3(0.0, h, 0.0);
+    let dz = vec3(0.0, 0.0, h);
+    vec3(
+      self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+      self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+      self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+    )
+    .unit()
+  }
+
+  // Where the ray is inside the bounding box, clipped to [t_min, t_max]
+  fn box_span(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+    for a in 0..3 {
+      let inv_d = r.inv_direction()[a];
+      let mut t0 = (self.boxy.min[a] - r.origin()[a]) * inv_d;
+      let mut t1 = (self.boxy.max[a] - r.origin()[a]) * inv_d;
+      if inv_d < 0.0 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+      t_min = t_min.max(t0);
+      t_max = t_max.min(t1);
+      if t_max <= t_min {
+        return None;
+      }
+    }
+    Some((t_min, t_max))
+  }
+
+  // Marches to the first surface crossing, as (t, point)
+  fn march(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3)> {
+    let (start, end) = self.box_span(r, t_min, t_max)?;
+    let speed = r.direction().length() * self.lipschitz;
+
+    // Starting inside means marching out to the far side of the surface. A
+    // ray leaving the surface it bounced off starts right on it, so it has to
+    // get clear before anything counts as a hit.
+    let p0 = r.point_at_parameter(start);
+    let d0 = self.sdf.distance(p0);
+    let mut leaving = d0.abs() < self.epsilon;
+    let sign = if !leaving {
+      d0.signum()
+    } else if self.normal(p0).dot(r.direction()) > 0.0 {
+      1.0
+    } else {
+      -1.0
+    };
+
+    let mut t = start;
+    for _ in 0..MAX_STEPS {
+      let p = r.point_at_parameter(t);
+      let d = sign * self.sdf.distance(p);
+      if leaving {
+        leaving = d < self.epsilon;
+        t += d.max(self.epsilon) / speed;
+      } else if d < self.epsilon {
+        return Some((t, p));
+      } else {
+        t += d / speed;
+      }
+      if t > end {
+        break;
+      }
+    }
+    None
+  }
+}
+
+impl Hitable for SdfObject {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let (t, p) = self.march(r, t_min, t_max)?;
+    let normal = self.normal(p);
+    let (u, v) = get_sphere_uv(normal);
+    Some(HitRecord {
+      t,
+      u,
+      v,
+      p,
+      normal,
+      front_face: r.direction().dot(normal) < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  // The march already stops at the first crossing, this just skips the
+  // normal's six extra distance evaluations
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.march(r, t_min, t_max).is_some()
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    Some(self.boxy)
+  }
+}
@@ -0,0 +1,119 @@
+use std::cell::Cell;
+use std::fmt;
+use std::ops::AddAssign;
+
+use crate::vec3::*;
+
+// Traversal work, counted per thread as it happens so render() can split it
+// up by pixel without any locking
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counters {
+  pub box_tests: u64,
+  pub node_visits: u64,
+  pub primitive_tests: u64,
+}
+
+impl AddAssign for Counters {
+  fn add_assign(&mut self, other: Self) {
+    self.box_tests += other.box_tests;
+    self.node_visits += other.node_visits;
+    self.primitive_tests += other.primitive_tests;
+  }
+}
+
+thread_local! {
+  // Const so every count is a plain thread local access, no lazy setup check
+  static COUNTERS: Cell<Counters> = const {
+    Cell::new(Counters {
+      box_tests: 0,
+      node_visits: 0,
+      primitive_tests: 0,
+    })
+  };
+}
+
+// Only with the stats feature, otherwise every count compiles away and a
+// normal render pays nothing for them
+fn count(f: impl FnOnce(&mut Counters)) {
+  if cfg!(feature = "stats") {
+    COUNTERS.with(|cell| {
+      let mut counters = cell.get();
+      f(&mut counters);
+      cell.set(counters);
+    });
+  }
+}
+
+pub fn count_box_tests(boxes: u64) {
+  count(|c| c.box_tests += boxes);
+}
+
+pub fn count_node_visit() {
+  count(|c| c.node_visits += 1);
+}
+
+pub fn count_primitive_test() {
+  count(|c| c.primitive_tests += 1);
+}
+
+// Everything this thread counted since the last call
+pub fn take() -> Counters {
+  COUNTERS.with(Cell::take)
+}
+
+// Shape of a built tree, to tell a bad tree from a scene that's just big
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TreeStats {
+  pub depth: usize,
+  pub nodes: usize,
+  pub leaves: usize,
+  pub hitables: usize,
+  pub unbounded: usize,
+  pub sah_cost: f64,
+  pub bytes: usize,
+}
+
+impl fmt::Display for TreeStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} nodes, {} leaves, depth {}, {} hitables ({} unbounded), SAH cost {:.2}, {:.1} KiB",
+      self.nodes,
+      self.leaves,
+      self.depth,
+      self.hitables,
+      self.unbounded,
+      self.sah_cost,
+      self.bytes as f64 / 1024.0
+    )
+  }
+}
+
+// Blue through cyan, green and yellow to red as x goes from 0 to 1
+pub fn heat_color(x: f64) -> Vec3 {
+  let stops = [
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 1.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(1.0, 0.0, 0.0),
+  ];
+  let scaled = x.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+  let i = (scaled as usize).min(stops.len() - 2);
+  let f = scaled - i as f64;
+  scalar(1.0 - f) * stops[i] + scalar(f) * stops[i + 1]
+}
+
+// One value per pixel, top row first like render()'s buffer, scaled so the
+// largest is red
+pub fn heatmap(values: &[f64]) -> Vec<u8> {
+  let max = values.iter().cloned().fold(0.0, f64::max);
+  let mut buffer = Vec::with_capacity(values.len() * 3);
+  for &value in values {
+    let col = heat_color(if max > 0.0 { value / max } else { 0.0 });
+    buffer.push((255.99 * col.x) as u8);
+    buffer.push((255.99 * col.y) as u8);
+    buffer.push((255.99 * col.z) as u8);
+  }
+  buffer
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::mat4::Mat4;
+use crate::ray::Ray;
+
+// Top level of a two level BVH. Every Instance points at a bottom level tree
+// built once in object space (usually a FlatBvh), so moving instances around
+// only rebuilds this tree over their boxes, never the geometry under them.
+#[derive(Debug)]
+pub struct Tlas {
+  instances: Vec<Arc<Instance>>,
+  bvh: FlatBvh,
+  time0: f64,
+  time1: f64,
+}
+
+impl Tlas {
+  pub fn new(instances: Vec<Instance>, time0: f64, time1: f64) -> Self {
+    assert!(!instances.is_empty(), "No instances in Tlas::new()");
+    let instances = instances.into_iter().map(Arc::new).collect::<Vec<_>>();
+    Self {
+      bvh: Self::build(&instances, time0, time1),
+      instances,
+      time0,
+      time1,
+    }
+  }
+
+  fn build(instances: &[Arc<Instance>], time0: f64, time1: f64) -> FlatBvh {
+    let l = instances
+      .iter()
+      .map(|instance| Arc::clone(instance) as Arc<dyn Hitable>)
+      .collect::<Vec<_>>();
+    FlatBvh::from_tree(BvhNode::new(&l, time0, time1))
+  }
+
+  pub fn set_matrix(&mut self, index: usize, matrix: Mat4) {
+    self.set_matrices(&[(index, matrix)]);
+  }
+
+  // Moves any number of instances for the cost of one top level rebuild
+  pub fn set_matrices(&mut self, matrices: &[(usize, Mat4)]) {
+    for &(index, matrix) in matrices {
+      // The old tree still holds the instance, so this copies it, which only
+      // clones the pointer to its geometry
+      Arc::make_mut(&mut self.instances[index]).set_matrix(matrix);
+    }
+    self.bvh = Self::build(&self.instances, self.time0, self.time1);
+  }
+}
+
+impl Hitable for Tlas {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    self.bvh.hit(r, t_min, t_max)
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.bvh.occluded(r, t_min, t_max)
+  }
+
+  fn bounding_box(&self, t0: f64, t1: f64) -> Option<Aabb> {
+    self.bvh.bounding_box(t0, t1)
+  }
+}
@@ -0,0 +1,229 @@
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::material::*;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+const PI: f64 = std::f64::consts::PI;
+
+// Ring lies in the xz plane around `center`, like the quadrics it's up +y
+#[derive(Debug)]
+pub struct Torus {
+  pub center: Vec3,
+  pub major_radius: f64,
+  pub minor_radius: f64,
+  pub material: Material,
+}
+
+impl Torus {
+  pub fn new(center: Vec3, major_radius: f64, minor_radius: f64, material: Material) -> Self {
+    Self {
+      center,
+      major_radius,
+      minor_radius,
+      material,
+    }
+  }
+
+  // Every t the ray crosses the surface at, ascending
+  fn crossings(&self, r: &Ray) -> impl Iterator<Item = f64> {
+    let big_r = self.major_radius;
+    let small_r = self.minor_radius;
+
+    // The quartic's coefficients grow with the fourth power of the distance,
+    // so solve with a unit direction from a start point pulled up to the
+    // bounding sphere and convert back afterwards
+    let length = r.direction().length();
+    let d = r.direction() / scalar(length);
+    let mut o = r.origin() - self.center;
+    let bound = big_r + small_r;
+    let shift = (-o.dot(d) - bound).max(0.0);
+    o += scalar(shift) * d;
+
+    // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)
+    let od = o.dot(d);
+    let k = o.dot(o) + big_r * big_r - small_r * small_r;
+    let four_r2 = 4.0 * big_r * big_r;
+    let roots = solve_quartic(
+      4.0 * od,
+      4.0 * od * od + 2.0 * k - four_r2 * (d.x * d.x + d.z * d.z),
+      4.0 * od * k - 2.0 * four_r2 * (o.x * d.x + o.z * d.z),
+      k * k - four_r2 * (o.x * o.x + o.z * o.z),
+    );
+
+    roots.into_iter().map(move |root| (root + shift) / length)
+  }
+}
+
+impl Hitable for Torus {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let big_r = self.major_radius;
+    let t = self.crossings(r).find(|&t| t > t_min && t < t_max)?;
+
+    let p = r.point_at_parameter(t);
+    let local = p - self.center;
+    let ring_dist = (local.x * local.x + local.z * local.z).sqrt();
+    // Closest point on the ring running through the middle of the tube
+    let ring_point = vec3(local.x, 0.0, local.z) * scalar(big_r / ring_dist);
+
+    let mut u = local.z.atan2(local.x) / (2.0 * PI);
+    let mut v = local.y.atan2(ring_dist - big_r) / (2.0 * PI);
+    if u < 0.0 {
+      u += 1.0;
+    }
+    if v < 0.0 {
+      v += 1.0;
+    }
+
+    Some(HitRecord {
+      t,
+      u,
+      v,
+      p,
+      normal: (local - ring_point).unit(),
+      front_face: r.direction().dot(local - ring_point) < 0.0,
+      tangent: scalar(0.0),
+      material: Some(&self.material),
+    })
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    self.crossings(r).any(|t| t > t_min && t < t_max)
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    let extent = self.major_radius + self.minor_radius;
+    let half = vec3(extent, self.minor_radius, extent);
+    Some(Aabb::new(self.center - half, self.center + half))
+  }
+}
+
+// Real roots of x^4 + a x^3 + b x^2 + c x + d, ascending. Ferrari's method
+// gets close, then Newton's method on the original polynomial cleans up the
+// cancellation error so roots near t_min aren't misjudged.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+  // Depress with x = y - a/4 to y^4 + p y^2 + q y + s
+  let a2 = a * a;
+  let p = b - 3.0 * a2 / 8.0;
+  let q = c - a * b / 2.0 + a2 * a / 8.0;
+  let s = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+  let mut roots = vec![];
+  if q.abs() < 1e-12 {
+    // Biquadratic, solve for y^2
+    for z in solve_quadratic(1.0, p, s) {
+      if z >= 0.0 {
+        roots.push(z.sqrt());
+        roots.push(-z.sqrt());
+      }
+    }
+  } else {
+    // Any positive root of the resolvent cubic splits it into two quadratics
+    let m = solve_cubic(p, p * p / 4.0 - s, -q * q / 8.0)
+      .into_iter()
+      .fold(0.0, f64::max);
+    if m <= 0.0 {
+      return roots;
+    }
+    let sqrt_2m = (2.0 * m).sqrt();
+    let offset = q / (2.0 * sqrt_2m);
+    roots.extend(solve_quadratic(1.0, -sqrt_2m, p / 2.0 + m + offset));
+    roots.extend(solve_quadratic(1.0, sqrt_2m, p / 2.0 + m - offset));
+  }
+
+  let mut roots: Vec<f64> = roots
+    .into_iter()
+    .map(|y| polish_quartic_root(y - a / 4.0, a, b, c, d))
+    .filter(|x| x.is_finite())
+    .collect();
+  roots.sort_by(f64::total_cmp);
+  roots
+}
+
+fn polish_quartic_root(mut x: f64, a: f64, b: f64, c: f64, d: f64) -> f64 {
+  for _ in 0..4 {
+    let f = (((x + a) * x + b) * x + c) * x + d;
+    let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+    if df == 0.0 {
+      break;
+    }
+    let step = f / df;
+    x -= step;
+    if step.abs() < 1e-12 * x.abs().max(1.0) {
+      break;
+    }
+  }
+  x
+}
+
+// Real roots of a x^2 + b x + c, using the form that avoids cancellation
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+  let discriminant = b * b - 4.0 * a * c;
+  if discriminant < 0.0 {
+    return vec![];
+  }
+  let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+  if q == 0.0 {
+    return vec![0.0];
+  }
+  vec![q / a, c / q]
+}
+
+// Real roots of x^3 + a x^2 + b x + c
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+  let q = (a * a - 3.0 * b) / 9.0;
+  let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+  let q3 = q * q * q;
+
+  if r * r < q3 {
+    let theta = (r / q3.sqrt()).acos();
+    let scale = -2.0 * q.sqrt();
+    vec![
+      scale * (theta / 3.0).cos() - a / 3.0,
+      scale * ((theta + 2.0 * PI) / 3.0).cos() - a / 3.0,
+      scale * ((theta - 2.0 * PI) / 3.0).cos() - a / 3.0,
+    ]
+  } else {
+    let big_a = -r.signum() * (r.abs() + (r * r - q3).sqrt()).cbrt();
+    let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+    vec![big_a + big_b - a / 3.0]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+    assert_eq!(roots.len(), expected.len(), "roots {:?}", roots);
+    for (root, want) in roots.iter().zip(expected) {
+      assert!((root - want).abs() < 1e-9, "roots {:?}", roots);
+    }
+  }
+
+  #[test]
+  fn quartic_with_four_roots() {
+    // (x - 1)(x - 2)(x - 3)(x - 4)
+    assert_roots(
+      solve_quartic(-10.0, 35.0, -50.0, 24.0),
+      &[1.0, 2.0, 3.0, 4.0],
+    );
+    // (x + 0.5)(x - 0.25)(x - 10)(x - 100), far apart like a ray grazing a torus
+    assert_roots(
+      solve_quartic(-109.75, 972.375, 263.75, -125.0),
+      &[-0.5, 0.25, 10.0, 100.0],
+    );
+  }
+
+  #[test]
+  fn quartic_with_two_roots() {
+    // (x - 1)(x + 3)(x^2 + 1)
+    assert_roots(solve_quartic(2.0, -2.0, 2.0, -3.0), &[-3.0, 1.0]);
+  }
+
+  #[test]
+  fn quartic_with_no_roots() {
+    // (x^2 + 1)(x^2 + 4)
+    assert_roots(solve_quartic(0.0, 5.0, 0.0, 4.0), &[]);
+  }
+}
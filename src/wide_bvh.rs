@@ -0,0 +1,395 @@
+use std::sync::Arc;
+
+use crate::bvh::*;
+use crate::hitable::*;
+use crate::ray::Ray;
+use crate::stats;
+
+const WIDTH: usize = 4;
+// Every node visited pushes at most WIDTH - 1 more than it pops, and the
+// binary tree it comes from is at most 64 deep
+const STACK_SIZE: usize = 256;
+// pbrt's allowance for the rounding in the f32 slab test, see its
+// Bounds3::IntersectP
+const ROUND_UP: f32 = 1.0 + 4.0 * f32::EPSILON;
+
+// Four children's boxes, laid out a row per bound so each row fills an SSE
+// register and all four slab tests run at once
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(16))]
+struct WideNode {
+  // min x, y, z, then max x, y, z
+  bounds: [[f32; WIDTH]; 6],
+  // Index of a node, or where a leaf's hitables start
+  children: [u32; WIDTH],
+  // How many hitables a leaf has, zero for nodes
+  counts: [u32; WIDTH],
+}
+
+impl WideNode {
+  // Unused slots get an inside out box, which no ray can pass through
+  fn empty() -> Self {
+    let mut bounds = [[f32::INFINITY; WIDTH]; 6];
+    for row in bounds[3..].iter_mut() {
+      *row = [f32::NEG_INFINITY; WIDTH];
+    }
+    Self {
+      bounds,
+      children: [0; WIDTH],
+      counts: [0; WIDTH],
+    }
+  }
+
+  fn set_box(&mut self, slot: usize, boxy: Aabb) {
+    for a in 0..3 {
+      self.bounds[a][slot] = round_down(boxy.min[a]);
+      self.bounds[3 + a][slot] = round_up(boxy.max[a]);
+    }
+  }
+}
+
+// Boxes only shrink going to f32 if they're rounded outwards
+fn round_down(x: f64) -> f32 {
+  let y = x as f32;
+  if y as f64 > x {
+    y.next_down()
+  } else {
+    y
+  }
+}
+
+fn round_up(x: f64) -> f32 {
+  let y = x as f32;
+  if (y as f64) < x {
+    y.next_up()
+  } else {
+    y
+  }
+}
+
+// The ray set up for f32 box tests
+struct WideRay {
+  origin: [f32; 3],
+  inv_direction: [f32; 3],
+  // The origin moves a little going to f32, this much t covers it
+  slack: [f32; 3],
+  // Which rows of bounds the ray meets first and last along each axis
+  near: [usize; 3],
+  far: [usize; 3],
+}
+
+impl WideRay {
+  fn new(r: &Ray) -> Self {
+    let mut ray = Self {
+      origin: [0.0; 3],
+      inv_direction: [0.0; 3],
+      slack: [0.0; 3],
+      near: [0, 1, 2],
+      far: [3, 4, 5],
+    };
+    for a in 0..3 {
+      let o = r.origin()[a];
+      // Kept finite, an infinity times a zero distance would be NaN
+      let inv = (r.inv_direction()[a] as f32).clamp(-f32::MAX, f32::MAX);
+      ray.origin[a] = o as f32;
+      ray.inv_direction[a] = inv;
+      ray.slack[a] = (o.abs() as f32 * f32::EPSILON * inv.abs()).min(f32::MAX);
+      if inv < 0.0 {
+        ray.near[a] = 3 + a;
+        ray.far[a] = a;
+      }
+    }
+    ray
+  }
+}
+
+// Which of the four boxes the ray passes through inside [t_min, t_max], as a
+// bit mask, and where it enters each of them
+#[cfg(target_arch = "x86_64")]
+fn hit_boxes(node: &WideNode, ray: &WideRay, t_min: f32, t_max: f32) -> (u32, [f32; WIDTH]) {
+  use std::arch::x86_64::*;
+
+  // SSE2 is part of x86_64, so it's always there. The rows are 16 byte
+  // aligned by WideNode's repr.
+  unsafe {
+    let mut t_near = _mm_set1_ps(t_min);
+    let mut t_far = _mm_set1_ps(t_max);
+    for a in 0..3 {
+      let o = _mm_set1_ps(ray.origin[a]);
+      let inv = _mm_set1_ps(ray.inv_direction[a]);
+      let slack = _mm_set1_ps(ray.slack[a]);
+      let near = _mm_load_ps(node.bounds[ray.near[a]].as_ptr());
+      let far = _mm_load_ps(node.bounds[ray.far[a]].as_ptr());
+      let t0 = _mm_sub_ps(_mm_mul_ps(_mm_sub_ps(near, o), inv), slack);
+      let t1 = _mm_add_ps(_mm_mul_ps(_mm_sub_ps(far, o), inv), slack);
+      t_near = _mm_max_ps(t_near, t0);
+      t_far = _mm_min_ps(t_far, _mm_mul_ps(t1, _mm_set1_ps(ROUND_UP)));
+    }
+    let mask = _mm_movemask_ps(_mm_cmple_ps(t_near, t_far)) as u32;
+    let mut entries = [0.0; WIDTH];
+    _mm_storeu_ps(entries.as_mut_ptr(), t_near);
+    (mask, entries)
+  }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn hit_boxes(node: &WideNode, ray: &WideRay, t_min: f32, t_max: f32) -> (u32, [f32; WIDTH]) {
+  hit_boxes_scalar(node, ray, t_min, t_max)
+}
+
+// Same test a box at a time, for targets without SSE, and checked against
+// the SSE one in the tests
+fn hit_boxes_scalar(node: &WideNode, ray: &WideRay, t_min: f32, t_max: f32) -> (u32, [f32; WIDTH]) {
+  let mut mask = 0;
+  let mut entries = [0.0; WIDTH];
+  for (slot, entry) in entries.iter_mut().enumerate() {
+    let mut t_near = t_min;
+    let mut t_far = t_max;
+    for a in 0..3 {
+      let near = node.bounds[ray.near[a]][slot];
+      let far = node.bounds[ray.far[a]][slot];
+      let t0 = (near - ray.origin[a]) * ray.inv_direction[a] - ray.slack[a];
+      let t1 = (far - ray.origin[a]) * ray.inv_direction[a] + ray.slack[a];
+      t_near = t_near.max(t0);
+      t_far = t_far.min(t1 * ROUND_UP);
+    }
+    if t_near <= t_far {
+      mask |= 1 << slot;
+    }
+    *entry = t_near;
+  }
+  (mask, entries)
+}
+
+// A binary BVH collapsed so every node has up to four children, halving the
+// depth and testing the children's boxes together
+#[derive(Debug)]
+pub struct WideBvh {
+  nodes: Vec<WideNode>,
+  hitables: Vec<Arc<dyn Hitable>>,
+  unbounded: Vec<Arc<dyn Hitable>>,
+  boxy: Aabb,
+}
+
+impl WideBvh {
+  pub fn from_tree(mut tree: BvhNode) -> Self {
+    let mut wide = Self {
+      nodes: vec![],
+      hitables: vec![],
+      unbounded: std::mem::take(&mut tree.unbounded),
+      boxy: tree.boxy,
+    };
+    wide.collapse(tree);
+    wide
+  }
+
+  // Opens up the biggest inner node among the children until there are four
+  // of them, then does the same for each child that's still an inner node
+  fn collapse(&mut self, node: BvhNode) -> u32 {
+    let index = self.nodes.len();
+    self.nodes.push(WideNode::empty());
+
+    let mut children = vec![node];
+    while children.len() < WIDTH {
+      let biggest = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.left.is_some())
+        .max_by(|(_, a), (_, b)| {
+          a.boxy
+            .surface_area()
+            .partial_cmp(&b.boxy.surface_area())
+            .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(i, _)| i);
+      let Some(biggest) = biggest else {
+        break;
+      };
+      let inner = children.swap_remove(biggest);
+      children.push(*inner.left.expect("Inner BvhNode without a left child"));
+      children.push(*inner.right.expect("Inner BvhNode without a right child"));
+    }
+
+    for (slot, child) in children.into_iter().enumerate() {
+      self.nodes[index].set_box(slot, child.boxy);
+      if child.left.is_some() {
+        self.nodes[index].children[slot] = self.collapse(child);
+      } else {
+        self.nodes[index].children[slot] = self.hitables.len() as u32;
+        self.nodes[index].counts[slot] = child.contents.len() as u32;
+        self.hitables.extend(child.contents);
+      }
+    }
+    index as u32
+  }
+}
+
+impl Hitable for WideBvh {
+  fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let ray = WideRay::new(r);
+    let t_min_f32 = round_down(t_min);
+    let mut closest = t_max;
+    let mut result = None;
+    for hitable in self.unbounded.iter() {
+      stats::count_primitive_test();
+      if let Some(rec) = hitable.hit(r, t_min, closest) {
+        closest = rec.t;
+        result = Some(rec);
+      }
+    }
+    // Child or hitable index, hitable count, and where the ray enters it
+    let mut stack = [(0u32, 0u32, 0.0f32); STACK_SIZE];
+    let mut top = 0;
+    let mut node = &self.nodes[0];
+
+    loop {
+      stats::count_node_visit();
+      stats::count_box_tests(WIDTH as u64);
+      let (mask, entries) = hit_boxes(node, &ray, t_min_f32, round_up(closest));
+
+      // Pushed farthest first so the nearest child comes off next
+      let mut slots = [0usize; WIDTH];
+      let mut hits = 0;
+      for slot in (0..WIDTH).filter(|slot| mask & (1 << slot) != 0) {
+        slots[hits] = slot;
+        hits += 1;
+      }
+      slots[..hits].sort_unstable_by(|&a, &b| {
+        entries[b]
+          .partial_cmp(&entries[a])
+          .unwrap_or(std::cmp::Ordering::Equal)
+      });
+      for &slot in &slots[..hits] {
+        stack[top] = (node.children[slot], node.counts[slot], entries[slot]);
+        top += 1;
+      }
+
+      loop {
+        if top == 0 {
+          return result;
+        }
+        top -= 1;
+        let (child, count, entry) = stack[top];
+        // Something closer was found since this was pushed
+        if entry > round_up(closest) {
+          continue;
+        }
+        if count == 0 {
+          node = &self.nodes[child as usize];
+          break;
+        }
+        let start = child as usize;
+        for hitable in &self.hitables[start..start + count as usize] {
+          stats::count_primitive_test();
+          if let Some(rec) = hitable.hit(r, t_min, closest) {
+            closest = rec.t;
+            result = Some(rec);
+          }
+        }
+      }
+    }
+  }
+
+  fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    if self.unbounded.iter().any(|hitable| {
+      stats::count_primitive_test();
+      hitable.occluded(r, t_min, t_max)
+    }) {
+      return true;
+    }
+
+    let ray = WideRay::new(r);
+    let (t_min_f32, t_max_f32) = (round_down(t_min), round_up(t_max));
+    let mut stack = [(0u32, 0u32); STACK_SIZE];
+    let mut top = 0;
+    let mut node = &self.nodes[0];
+
+    loop {
+      stats::count_node_visit();
+      stats::count_box_tests(WIDTH as u64);
+      let (mask, _) = hit_boxes(node, &ray, t_min_f32, t_max_f32);
+      for slot in (0..WIDTH).filter(|slot| mask & (1 << slot) != 0) {
+        stack[top] = (node.children[slot], node.counts[slot]);
+        top += 1;
+      }
+
+      loop {
+        if top == 0 {
+          return false;
+        }
+        top -= 1;
+        let (child, count) = stack[top];
+        if count == 0 {
+          node = &self.nodes[child as usize];
+          break;
+        }
+        let start = child as usize;
+        let hitables = &self.hitables[start..start + count as usize];
+        if hitables.iter().any(|hitable| {
+          stats::count_primitive_test();
+          hitable.occluded(r, t_min, t_max)
+        }) {
+          return true;
+        }
+      }
+    }
+  }
+
+  fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<Aabb> {
+    if self.unbounded.is_empty() {
+      Some(self.boxy)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vec3::*;
+  use rand::prelude::*;
+
+  fn random_vec3(rng: &mut StdRng, size: f64) -> Vec3 {
+    vec3(
+      rng.gen_range(-size, size),
+      rng.gen_range(-size, size),
+      rng.gen_range(-size, size),
+    )
+  }
+
+  // The fallback only runs off x86_64, so check it against SSE here
+  #[test]
+  fn scalar_boxes_match_sse() {
+    let mut rng = StdRng::seed_from_u64(46);
+    for _ in 0..10000 {
+      let mut node = WideNode::empty();
+      // The last slot is left empty now and then
+      let slots = if rng.gen::<f64>() < 0.2 {
+        WIDTH - 1
+      } else {
+        WIDTH
+      };
+      for slot in 0..slots {
+        let min = random_vec3(&mut rng, 10.0);
+        let size = random_vec3(&mut rng, 5.0);
+        node.set_box(slot, Aabb::new(min, min + scalar(5.0) + size));
+      }
+      let mut direction = random_vec3(&mut rng, 1.0);
+      // Axis aligned rays take the clamped infinite inverse
+      if rng.gen::<f64>() < 0.2 {
+        direction[rng.gen_range(0, 3)] = 0.0;
+      }
+      let ray = WideRay::new(&Ray::new(random_vec3(&mut rng, 20.0), direction));
+      let t_max = if rng.gen::<f64>() < 0.5 {
+        f32::MAX
+      } else {
+        rng.gen_range(0.0, 30.0)
+      };
+      assert_eq!(
+        hit_boxes(&node, &ray, 0.001, t_max),
+        hit_boxes_scalar(&node, &ray, 0.001, t_max)
+      );
+    }
+  }
+}